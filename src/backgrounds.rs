@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::media::{is_image_file, probe_media};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundKind {
+    Image,
+    Video,
+}
+
+// A background kept on the server, described by a `<id>.json` file next to
+// the media itself, `<id>.media.<ext>`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Background {
+    pub id: String,
+    // Name of the file as it was uploaded
+    pub name: String,
+    // Media file inside the library directory
+    pub filename: String,
+    pub kind: BackgroundKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+pub enum BackgroundSource {
+    // Sent with the request and saved into its workspace; removed with it
+    Upload(String),
+    // Path of a library background or other file that is left in place
+    Library(String),
+}
+
+// A file being streamed into the library, not listed until it is finished
+pub struct PendingUpload {
+    id: String,
+    name: String,
+    pub path: PathBuf,
+}
+
+pub struct BackgroundLibrary {
+    dir: PathBuf,
+}
+
+impl BackgroundLibrary {
+    pub fn new(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(BackgroundLibrary { dir: dir.to_path_buf() })
+    }
+
+    pub fn start_upload(&self, name: &str) -> PendingUpload {
+        let id = uuid::Uuid::new_v4().to_string();
+        let ext: String = Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("bin")
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+
+        // Never `<id>.json`, whatever the client called the file
+        PendingUpload {
+            path: self.dir.join(format!("{}.media.{}", id, ext)),
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    // Probes the uploaded file and adds it to the library. Files ffprobe cannot
+    // read are removed again.
+    pub async fn finish_upload(&self, upload: PendingUpload) -> Result<Background> {
+        let path = upload.path.to_string_lossy().to_string();
+        let info = match probe_media(&path).await {
+            Ok(info) => info,
+            Err(e) => {
+                self.abort_upload(upload);
+                return Err(e);
+            }
+        };
+
+        let is_image = is_image_file(&path);
+        let background = Background {
+            filename: upload
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            id: upload.id,
+            name: upload.name,
+            kind: if is_image {
+                BackgroundKind::Image
+            } else {
+                BackgroundKind::Video
+            },
+            duration: if is_image { None } else { info.duration },
+            width: info.width,
+            height: info.height,
+            size: std::fs::metadata(&upload.path)?.len(),
+            created_at: Utc::now(),
+        };
+
+        std::fs::write(
+            self.metadata_path(&background.id),
+            serde_json::to_string_pretty(&background)?,
+        )?;
+
+        Ok(background)
+    }
+
+    pub fn abort_upload(&self, upload: PendingUpload) {
+        let _ = std::fs::remove_file(&upload.path);
+    }
+
+    // Newest first
+    pub fn list(&self) -> Vec<Background> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let mut backgrounds: Vec<Background> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+            .filter(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| uuid::Uuid::parse_str(stem).is_ok())
+            })
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .filter_map(|json| serde_json::from_str(&json).ok())
+            .collect();
+
+        backgrounds.sort_by_key(|background| std::cmp::Reverse(background.created_at));
+        backgrounds
+    }
+
+    pub fn get(&self, id: &str) -> Option<Background> {
+        // Ids are UUIDs, which also keeps them from naming other paths
+        uuid::Uuid::parse_str(id).ok()?;
+        let json = std::fs::read_to_string(self.metadata_path(id)).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn path(&self, background: &Background) -> String {
+        self.dir.join(&background.filename).to_string_lossy().to_string()
+    }
+
+    // False when there was no such background
+    pub fn delete(&self, id: &str) -> Result<bool> {
+        let Some(background) = self.get(id) else {
+            return Ok(false);
+        };
+
+        std::fs::remove_file(self.metadata_path(id))?;
+        if let Err(e) = std::fs::remove_file(self.path(&background)) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(anyhow!("Could not delete {}: {}", background.filename, e));
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn metadata_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
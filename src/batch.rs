@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+
+use reddit_video_maker::workspace::upload_path;
+use zip::{CompressionMethod, ZipWriter};
+
+// Keeps one request from monopolising the render queue
+pub const MAX_BATCH_ENTRIES: usize = 100;
+
+// Entries use the same field names as the /create-video form
+pub type BatchEntry = HashMap<String, String>;
+
+pub struct BatchInput {
+    // Applied to every entry unless the entry sets the field itself
+    pub defaults: BatchEntry,
+    pub entries: Vec<BatchEntry>,
+}
+
+#[derive(Deserialize)]
+struct BatchJson {
+    #[serde(default)]
+    defaults: HashMap<String, Value>,
+    entries: Vec<HashMap<String, Value>>,
+}
+
+impl BatchInput {
+    // Fields for one entry, with the defaults filled in
+    pub fn fields(&self, entry: &BatchEntry) -> BatchEntry {
+        let mut fields = self.defaults.clone();
+        fields.extend(entry.iter().map(|(k, v)| (k.clone(), v.clone())));
+        fields
+    }
+}
+
+// JSON is `{"defaults": {...}, "entries": [{...}, ...]}`; CSV has a header
+// row naming the fields and one entry per row
+pub fn parse_batch(body: &[u8], is_csv: bool) -> Result<BatchInput> {
+    let input = if is_csv {
+        BatchInput {
+            defaults: HashMap::new(),
+            entries: parse_csv(body)?,
+        }
+    } else {
+        let json: BatchJson =
+            serde_json::from_slice(body).map_err(|e| anyhow!("Invalid batch: {}", e))?;
+        BatchInput {
+            defaults: string_fields(json.defaults),
+            entries: json.entries.into_iter().map(string_fields).collect(),
+        }
+    };
+
+    if input.entries.is_empty() {
+        return Err(anyhow!("The batch has no entries"));
+    }
+    if input.entries.len() > MAX_BATCH_ENTRIES {
+        return Err(anyhow!(
+            "A batch can have at most {} entries, got {}",
+            MAX_BATCH_ENTRIES,
+            input.entries.len()
+        ));
+    }
+
+    Ok(input)
+}
+
+fn parse_csv(body: &[u8]) -> Result<Vec<BatchEntry>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::Headers).from_reader(body);
+    let headers = reader.headers()?.clone();
+
+    let mut entries = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| anyhow!("Invalid CSV row {}: {}", i + 1, e))?;
+        // Empty cells fall back to the defaults
+        entries.push(
+            headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(_, value)| !value.trim().is_empty())
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        );
+    }
+
+    Ok(entries)
+}
+
+// Form fields are strings, so numbers and booleans are written out and nulls
+// left unset
+fn string_fields(values: HashMap<String, Value>) -> BatchEntry {
+    values
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            Value::Null => None,
+            Value::String(value) => Some((name, value)),
+            value => Some((name, value.to_string())),
+        })
+        .collect()
+}
+
+// Packs the files of each entry into a folder named after its number, e.g.
+// 03/video.mp4
+pub fn write_zip(path: &Path, entries: &[(usize, Vec<String>)]) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+
+    for (number, files) in entries {
+        for filename in files {
+            let source = upload_path(filename);
+            let Ok(mut file) = File::open(&source) else {
+                log::warn!("Skipping {} in batch download, it no longer exists", filename);
+                continue;
+            };
+
+            // Videos are already compressed
+            let method = if filename.ends_with(".mp4") {
+                CompressionMethod::Stored
+            } else {
+                CompressionMethod::Deflated
+            };
+            let options = SimpleFileOptions::default()
+                .compression_method(method)
+                .large_file(file.metadata()?.len() >= u32::MAX as u64);
+
+            let name = Path::new(filename)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| filename.clone());
+            zip.start_file(format!("{:02}/{}", number, name), options)?;
+            std::io::copy(&mut file, &mut zip)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_batch_reads_json_and_stringifies_values() {
+        let body = br#"{
+            "defaults": {"aspect": "9:16", "speed": 1.2},
+            "entries": [
+                {"text": "First story"},
+                {"text": "Second story", "aspect": "16:9", "voice": null}
+            ]
+        }"#;
+        let input = parse_batch(body, false).unwrap();
+
+        assert_eq!(input.entries.len(), 2);
+        assert_eq!(input.defaults["speed"], "1.2");
+        assert!(!input.entries[1].contains_key("voice"));
+
+        let first = input.fields(&input.entries[0]);
+        assert_eq!(first["aspect"], "9:16");
+        assert_eq!(first["text"], "First story");
+        let second = input.fields(&input.entries[1]);
+        assert_eq!(second["aspect"], "16:9");
+    }
+
+    #[test]
+    fn parse_batch_reads_csv_and_skips_empty_cells() {
+        let body = b" text , aspect\nFirst story,9:16\n\"Second, with a comma\",\n";
+        let input = parse_batch(body, true).unwrap();
+
+        assert_eq!(input.entries.len(), 2);
+        assert_eq!(input.entries[0]["aspect"], "9:16");
+        assert_eq!(input.entries[1]["text"], "Second, with a comma");
+        assert!(!input.entries[1].contains_key("aspect"));
+    }
+
+    #[test]
+    fn parse_batch_rejects_empty_and_oversized_batches() {
+        assert!(parse_batch(br#"{"entries": []}"#, false).is_err());
+        assert!(parse_batch(b"text\n", true).is_err());
+        assert!(parse_batch(b"not json", false).is_err());
+
+        let entries = vec![r#"{"text": "x"}"#; MAX_BATCH_ENTRIES + 1].join(",");
+        let body = format!(r#"{{"entries": [{}]}}"#, entries);
+        let error = parse_batch(body.as_bytes(), false).err().unwrap();
+        assert!(error.to_string().contains("at most"));
+    }
+}
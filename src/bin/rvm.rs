@@ -0,0 +1,365 @@
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use reddit_video_maker::captions::{
+    build_cues, read_word_timings, resolve_word_timings, to_ass, to_json, to_srt, to_vtt,
+    word_timings_filename, CaptionAlignment, CaptionFormat, CaptionStyle,
+};
+use reddit_video_maker::loudness::{
+    LoudnessTarget, DEFAULT_TARGET_LUFS, DEFAULT_TRUE_PEAK, MAX_TARGET_LUFS, MAX_TRUE_PEAK,
+    MIN_TARGET_LUFS, MIN_TRUE_PEAK,
+};
+use reddit_video_maker::media::probe_duration;
+use reddit_video_maker::music::{
+    MusicOptions, MusicSource, DEFAULT_MUSIC_FADE, DEFAULT_MUSIC_VOLUME, MAX_MUSIC_FADE,
+    MAX_MUSIC_VOLUME,
+};
+use reddit_video_maker::config::Config;
+use reddit_video_maker::render::check_range;
+use reddit_video_maker::tts::{
+    generate_tts_audio, TtsRegistry, DEFAULT_LANG, DEFAULT_VOICE, MAX_SPEED, MIN_SPEED,
+};
+use reddit_video_maker::video::{
+    BackgroundStart, FitMode, Motion, RenderEvent, MAX_PART_SECONDS, MIN_PART_SECONDS,
+};
+use reddit_video_maker::workspace::{set_upload_dir, upload_path, Workspace};
+use reddit_video_maker::RenderRequest;
+
+#[derive(Parser)]
+#[command(name = "rvm", version, about = "Render Reddit story videos without the web server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Narrate a script to an MP3")]
+    Tts {
+        #[command(flatten)]
+        voice: VoiceArgs,
+        #[arg(short, long, help = "Where to write the MP3; word timings go next to it as .words.json")]
+        output: PathBuf,
+    },
+    #[command(about = "Write captions for existing narration")]
+    Captions {
+        #[arg(long, help = "Script that was narrated")]
+        text: PathBuf,
+        #[arg(long, help = "The narration audio")]
+        audio: PathBuf,
+        #[arg(long, default_value = "auto")]
+        align: CaptionAlignment,
+        #[arg(long, default_value = "plain", help = "Only affects ASS output")]
+        style: CaptionStyle,
+        #[arg(long, default_value = "16:9", value_parser = ["16:9", "9:16"])]
+        aspect: String,
+        #[arg(short, long, help = "Caption file; the format follows the extension (srt, vtt, ass, json)")]
+        output: PathBuf,
+    },
+    #[command(about = "Narrate a script and render the full video")]
+    Render(RenderArgs),
+}
+
+#[derive(Args)]
+struct VoiceArgs {
+    #[arg(long, help = "Script to narrate")]
+    text: PathBuf,
+    #[arg(long, default_value = DEFAULT_LANG)]
+    lang: String,
+    #[arg(long, default_value = DEFAULT_VOICE)]
+    voice: String,
+    #[arg(long, default_value_t = 1.0, value_parser = bounded(MIN_SPEED, MAX_SPEED))]
+    speed: f32,
+    #[arg(long, help = "TTS engine id; picked automatically when left out")]
+    engine: Option<String>,
+}
+
+#[derive(Args)]
+struct RenderArgs {
+    #[command(flatten)]
+    voice: VoiceArgs,
+    #[arg(long, help = "Use this narration instead of running TTS")]
+    audio: Option<PathBuf>,
+    #[arg(long, help = "Background image or video")]
+    bg: PathBuf,
+    #[arg(long, default_value = "16:9", value_parser = ["16:9", "9:16"])]
+    aspect: String,
+    #[arg(long, default_value = "pad")]
+    fit: FitMode,
+    #[arg(long, default_value = "none", help = "Pan and zoom for image backgrounds")]
+    motion: Motion,
+    #[arg(long, help = "Seconds into a video background, or \"random\"")]
+    bg_start: Option<BackgroundStart>,
+    #[arg(long, default_value = "auto")]
+    caption_align: CaptionAlignment,
+    #[arg(long, default_value = "plain")]
+    caption_style: CaptionStyle,
+    #[arg(long, help = "Background music track")]
+    music: Option<PathBuf>,
+    #[arg(long, default_value_t = DEFAULT_MUSIC_VOLUME, value_parser = bounded(0.0, MAX_MUSIC_VOLUME))]
+    music_volume: f64,
+    #[arg(long, default_value_t = DEFAULT_MUSIC_FADE, value_parser = bounded(0.0, MAX_MUSIC_FADE))]
+    music_fade: f64,
+    #[arg(
+        long,
+        default_value_t = DEFAULT_TARGET_LUFS,
+        allow_hyphen_values = true,
+        value_parser = bounded(MIN_TARGET_LUFS, MAX_TARGET_LUFS)
+    )]
+    loudness_target: f64,
+    #[arg(
+        long,
+        default_value_t = DEFAULT_TRUE_PEAK,
+        allow_hyphen_values = true,
+        value_parser = bounded(MIN_TRUE_PEAK, MAX_TRUE_PEAK)
+    )]
+    true_peak: f64,
+    #[arg(
+        long,
+        help = "Split into parts of at most this many seconds",
+        value_parser = bounded(MIN_PART_SECONDS, MAX_PART_SECONDS)
+    )]
+    max_part_seconds: Option<f64>,
+    #[arg(long, help = "Also write the captions next to the video")]
+    captions: bool,
+    #[arg(short, long, help = "Where to write the MP4; parts get a _partN suffix")]
+    output: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+
+    // Same settings as the server, so renders come out the same
+    let config = Config::load()?;
+
+    // The pipeline works inside the upload directory, like the server
+    set_upload_dir(&config.server.upload_dir);
+    std::fs::create_dir_all(&config.server.upload_dir)?;
+
+    let registry = TtsRegistry::new(
+        &config.tts.piper_model_dir,
+        &config.tts.piper_binary,
+        config.tts.base_wpm,
+    );
+
+    match cli.command {
+        Command::Tts { voice, output } => {
+            let workspace = Workspace::create()?;
+            let filename = narrate(&registry, &workspace, &voice).await?;
+            move_file(&upload_path(&filename), &output)?;
+            eprintln!("Wrote {}", output.display());
+
+            // Picked up by `rvm captions` and `rvm render --audio`
+            let timings = upload_path(&word_timings_filename(&filename));
+            if Path::new(&timings).exists() {
+                let target = output.with_extension("words.json");
+                move_file(&timings, &target)?;
+                eprintln!("Wrote {}", target.display());
+            }
+        }
+        Command::Captions {
+            text,
+            audio,
+            align,
+            style,
+            aspect,
+            output,
+        } => {
+            let text = read_script(&text)?;
+            let audio_path = audio.to_string_lossy().to_string();
+            let tts_words = read_word_timings(&audio.with_extension("words.json"));
+
+            let duration = probe_duration(&audio_path).await?;
+            let words = resolve_word_timings(align, tts_words, &audio_path, &text, duration).await;
+            let cues = build_cues(&words);
+
+            let content = match caption_format(&output)? {
+                CaptionFormat::Srt => to_srt(&cues),
+                CaptionFormat::Vtt => to_vtt(&cues),
+                CaptionFormat::Ass => to_ass(
+                    &cues,
+                    &aspect,
+                    style == CaptionStyle::Karaoke,
+                    config.video.output_settings().font_size(&aspect),
+                ),
+                CaptionFormat::Json => to_json(&cues)?,
+            };
+            std::fs::write(&output, content)?;
+            eprintln!("Wrote {} caption segments to {}", cues.len(), output.display());
+        }
+        Command::Render(args) => render(&registry, &config, args).await?,
+    }
+
+    Ok(())
+}
+
+async fn render(registry: &TtsRegistry, config: &Config, args: RenderArgs) -> Result<()> {
+    let text = read_script(&args.voice.text)?;
+
+    // Read in place; backgrounds can be hour-long clips
+    if !args.bg.is_file() {
+        return Err(anyhow!("Could not read {}", args.bg.display()));
+    }
+
+    let mut builder = RenderRequest::builder()
+        .output(config.video.output_settings())
+        .text(text)
+        .lang(&args.voice.lang)
+        .voice(&args.voice.voice)
+        .speed(args.voice.speed)
+        .background_file(&args.bg)
+        .aspect_ratio(&args.aspect)
+        .fit_mode(args.fit)
+        .motion(args.motion)
+        .bg_start(args.bg_start.unwrap_or_default())
+        .caption_align(args.caption_align)
+        .caption_style(args.caption_style)
+        .loudness(LoudnessTarget {
+            integrated: args.loudness_target,
+            true_peak: args.true_peak,
+        });
+    if let Some(ref engine) = args.voice.engine {
+        builder = builder.engine(engine);
+    }
+    if let Some(ref path) = args.music {
+        builder = builder.music(MusicOptions {
+            source: MusicSource::Library(path.to_string_lossy().to_string()),
+            volume: args.music_volume,
+            fade: args.music_fade,
+        });
+    }
+    if let Some(seconds) = args.max_part_seconds {
+        builder = builder.max_part_seconds(seconds);
+    }
+
+    // The pipeline reads given narration from uploads/; the copy goes away
+    // with the workspace
+    let workspace = Workspace::create()?;
+    if let Some(ref audio) = args.audio {
+        let ext = audio.extension().and_then(|ext| ext.to_str()).unwrap_or("mp3");
+        let name = format!("narration.{}", ext);
+        std::fs::copy(audio, workspace.path(&name))
+            .with_context(|| format!("Could not read {}", audio.display()))?;
+        // Timings written by `rvm tts` travel with the narration
+        let timings = audio.with_extension("words.json");
+        if timings.is_file() {
+            std::fs::copy(&timings, upload_path(&word_timings_filename(&workspace.file(&name))))
+                .with_context(|| format!("Could not read {}", timings.display()))?;
+        }
+        builder = builder.audio(workspace.file(&name));
+    }
+
+    let report = |event: RenderEvent| match event {
+        RenderEvent::Stage(stage) => eprintln!("{:?}", stage),
+        RenderEvent::Progress(percent) => eprint!("\r{:5.1}%", percent),
+    };
+    let result = match builder.build() {
+        Ok(request) => reddit_video_maker::render(registry, request, &report).await,
+        Err(e) => Err(e),
+    };
+    eprintln!();
+
+    let output = result?;
+    if !output.engines.is_empty() {
+        eprintln!("Narrated with {}", output.engines.join(", "));
+    }
+
+    let part_count = output.parts.len();
+    for (i, part) in output.parts.iter().enumerate() {
+        let target = if part_count > 1 {
+            with_suffix(&args.output, &format!("_part{}", i + 1), "mp4")
+        } else {
+            args.output.clone()
+        };
+        move_file(&upload_path(&part.video_filename), &target)?;
+        eprintln!("Wrote {}", target.display());
+
+        for file in &part.caption_files {
+            let source = upload_path(&file.filename);
+            if args.captions {
+                // Named after the video they belong to, e.g. out_part1.srt
+                move_file(&source, &with_suffix(&target, "", file.format.extension()))?;
+            } else {
+                let _ = std::fs::remove_file(source);
+            }
+        }
+    }
+    let _ = std::fs::remove_dir(upload_path(&output.workspace_id));
+
+    Ok(())
+}
+
+// Runs TTS and returns the MP3's file name inside uploads/
+async fn narrate(registry: &TtsRegistry, workspace: &Workspace, voice: &VoiceArgs) -> Result<String> {
+    let text = read_script(&voice.text)?;
+    let output = generate_tts_audio(
+        registry,
+        workspace,
+        &text,
+        &voice.lang,
+        &voice.voice,
+        voice.speed,
+        voice.engine.as_deref(),
+    )
+    .await?;
+
+    eprintln!("Narrated with {}", output.engine);
+    Ok(output.filename)
+}
+
+// Parses a number that must lie within the pipeline's own limits, so a bad
+// flag is a usage error before anything runs
+fn bounded<T>(min: T, max: T) -> impl Fn(&str) -> std::result::Result<T, String> + Clone
+where
+    T: FromStr + PartialOrd + Display + Copy + Send + Sync + 'static,
+{
+    move |value| {
+        let number: T = value.trim().parse().map_err(|_| format!("{} is not a number", value))?;
+        check_range("value", number, min, max).map_err(|e| e.to_string())?;
+        Ok(number)
+    }
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    if text.trim().is_empty() {
+        return Err(anyhow!("{} is empty", path.display()));
+    }
+    Ok(text)
+}
+
+fn caption_format(path: &Path) -> Result<CaptionFormat> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("srt")
+        .to_lowercase();
+    CaptionFormat::ALL
+        .into_iter()
+        .find(|format| format.extension() == ext)
+        .ok_or_else(|| anyhow!("Unknown caption format: .{}", ext))
+}
+
+// out.mp4 + ("_part2", "mp4") -> out_part2.mp4
+fn with_suffix(path: &Path, suffix: &str, ext: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}{}.{}", stem, suffix, ext))
+}
+
+// Renames when possible, copying across filesystems
+fn move_file(from: &str, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to).with_context(|| format!("Could not write {}", to.display()))?;
+        let _ = std::fs::remove_file(from);
+    }
+    Ok(())
+}
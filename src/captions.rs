@@ -1,65 +1,483 @@
-use anyhow::Result;
-use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::process::Command;
 
-pub fn generate_captions_from_text(text: &str, audio_duration: f64) -> Result<(String, String)> {
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let total_words = words.len();
+use crate::workspace::upload_path;
+
+// Caption sizes before scaling, for 16:9 and 9:16 videos
+pub const DEFAULT_CAPTION_FONT_SIZE: u32 = 32;
+pub const DEFAULT_CAPTION_FONT_SIZE_VERTICAL: u32 = 36;
+
+// Quieter than this for at least SILENCE_MIN_SECONDS counts as a pause
+const SILENCE_NOISE_DB: i32 = -35;
+const SILENCE_MIN_SECONDS: f64 = 0.25;
+// How far (as a fraction of the whole text) a pause may sit from a sentence
+// boundary and still be snapped to it
+const SENTENCE_SNAP_TOLERANCE: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum CaptionAlignment {
+    // TTS word timings when recorded, otherwise silence detection
+    #[default]
+    Auto,
+    Tts,
+    Silence,
+    Linear,
+}
 
-    if total_words == 0 {
-        return Ok(("".to_string(), "No text to generate captions".to_string()));
+impl std::str::FromStr for CaptionAlignment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(CaptionAlignment::Auto),
+            "tts" => Ok(CaptionAlignment::Tts),
+            "silence" => Ok(CaptionAlignment::Silence),
+            "linear" => Ok(CaptionAlignment::Linear),
+            _ => Err(anyhow!("Unknown caption alignment: {}", s)),
+        }
     }
+}
 
-    let words_per_second = total_words as f64 / audio_duration;
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum CaptionStyle {
+    // SRT segments burned with a single style
+    #[default]
+    Plain,
+    // ASS with the spoken word highlighted
+    Karaoke,
+}
 
-    // Create segments of 3-6 words for TikTok-style captions
-    let mut segments = Vec::new();
-    let mut current_segment = Vec::new();
+impl std::str::FromStr for CaptionStyle {
+    type Err = anyhow::Error;
 
-    for word in words {
-        current_segment.push(word);
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "plain" => Ok(CaptionStyle::Plain),
+            "karaoke" => Ok(CaptionStyle::Karaoke),
+            _ => Err(anyhow!("Unknown caption style: {}", s)),
+        }
+    }
+}
 
-        // Create shorter segments for better readability
-        if current_segment.len() >= 4
-            && (word.ends_with(&['.', '!', '?', ',']) || current_segment.len() >= 6)
-        {
-            segments.push(current_segment.join(" "));
-            current_segment.clear();
-        } else if current_segment.len() >= 6 {
-            segments.push(current_segment.join(" "));
-            current_segment.clear();
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WordTiming {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+// Picks word timings for the captions according to `alignment`, falling back
+// to spreading the text evenly over the audio when nothing better is available.
+// `tts_words` are the timings saved when the narration was synthesized.
+pub async fn resolve_word_timings(
+    alignment: CaptionAlignment,
+    tts_words: Option<Vec<WordTiming>>,
+    audio_path: &str,
+    text: &str,
+    duration: f64,
+) -> Vec<WordTiming> {
+    if matches!(alignment, CaptionAlignment::Auto | CaptionAlignment::Tts) {
+        if let Some(mut words) = tts_words {
+            for word in &mut words {
+                word.end = word.end.min(duration);
+            }
+            return words;
         }
     }
 
-    // Add remaining words
-    if !current_segment.is_empty() {
-        segments.push(current_segment.join(" "));
+    if matches!(alignment, CaptionAlignment::Auto | CaptionAlignment::Silence) {
+        match align_with_silence(audio_path, text, duration).await {
+            Ok(words) => return words,
+            Err(e) => log::warn!("Silence alignment failed, using linear captions: {}", e),
+        }
     }
 
-    // Create SRT content
-    let mut srt_content = String::new();
-    let mut current_word_index = 0;
+    distribute_words(text, 0.0, duration)
+}
 
-    for (i, segment) in segments.iter().enumerate() {
-        let segment_words: Vec<&str> = segment.split_whitespace().collect();
-        let words_in_segment = segment_words.len();
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionFormat {
+    Srt,
+    Vtt,
+    Ass,
+    Json,
+}
+
+impl CaptionFormat {
+    pub const ALL: [CaptionFormat; 4] = [
+        CaptionFormat::Srt,
+        CaptionFormat::Vtt,
+        CaptionFormat::Ass,
+        CaptionFormat::Json,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CaptionFormat::Srt => "srt",
+            CaptionFormat::Vtt => "vtt",
+            CaptionFormat::Ass => "ass",
+            CaptionFormat::Json => "json",
+        }
+    }
+}
+
+// One caption shown on screen, with the timings of the words it contains
+#[derive(Clone, Debug, Serialize)]
+pub struct CaptionCue {
+    pub index: usize,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub words: Vec<WordTiming>,
+}
+
+pub struct CaptionFile {
+    pub format: CaptionFormat,
+    pub filename: String,
+}
 
-        let start_time = current_word_index as f64 / words_per_second;
-        let end_time =
-            ((current_word_index + words_in_segment) as f64 / words_per_second).min(audio_duration);
+// Groups words into cues of 3-6 words for TikTok-style captions
+pub fn build_cues(words: &[WordTiming]) -> Vec<CaptionCue> {
+    segment_words(words)
+        .into_iter()
+        .enumerate()
+        .map(|(i, segment)| CaptionCue {
+            index: i + 1,
+            start: segment[0].start,
+            end: segment[segment.len() - 1].end,
+            text: segment
+                .iter()
+                .map(|w| w.word.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            words: segment.to_vec(),
+        })
+        .collect()
+}
 
-        srt_content.push_str(&format!("{}\n", i + 1));
+pub fn to_srt(cues: &[CaptionCue]) -> String {
+    let mut srt_content = String::new();
+
+    for cue in cues {
+        srt_content.push_str(&format!("{}\n", cue.index));
         srt_content.push_str(&format!(
             "{} --> {}\n",
-            format_time(start_time),
-            format_time(end_time)
+            format_time(cue.start),
+            format_time(cue.end)
         ));
-        srt_content.push_str(&format!("{}\n\n", segment.to_uppercase()));
+        srt_content.push_str(&format!("{}\n\n", cue.text.to_uppercase()));
+    }
 
-        current_word_index += words_in_segment;
+    srt_content
+}
+
+pub fn to_vtt(cues: &[CaptionCue]) -> String {
+    let mut vtt_content = String::from("WEBVTT\n\n");
+
+    for cue in cues {
+        vtt_content.push_str(&format!(
+            "{} --> {}\n",
+            format_time(cue.start).replace(',', "."),
+            format_time(cue.end).replace(',', ".")
+        ));
+        vtt_content.push_str(&format!("{}\n\n", cue.text.to_uppercase()));
+    }
+
+    vtt_content
+}
+
+pub fn to_json(cues: &[CaptionCue]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(cues)?)
+}
+
+// ASS subtitles styled like the burned SRT captions. With `karaoke` each cue
+// stays on screen while the word being spoken is recoloured and enlarged.
+// `font_size` is the base size the burned SRT captions are given.
+pub fn to_ass(cues: &[CaptionCue], aspect_ratio: &str, karaoke: bool, font_size: u32) -> String {
+    let (play_res_x, play_res_y) = if aspect_ratio == "9:16" { (1080, 1920) } else { (1920, 1080) };
+
+    // Match the look of the burned SRT captions, whose sizes libass scales
+    // from a 288 pixel tall canvas
+    let scale = play_res_y as f32 / 288.0;
+    let font_size = (font_size as f32 * 0.7 * scale) as u32;
+    let base_margin_v = if aspect_ratio == "16:9" { 40 } else { 80 };
+    let margin_v = (base_margin_v as f32 * 0.7 * scale) as u32;
+
+    let mut ass = format!(
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 0\n\n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Default,Arial,{},&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,-1,0,0,0,100,100,0,0,1,{},{},2,40,40,{},1\n\n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        play_res_x,
+        play_res_y,
+        font_size,
+        (3.0 * scale).round(),
+        (2.0 * scale).round(),
+        margin_v
+    );
+
+    for cue in cues {
+        if !karaoke {
+            ass.push_str(&format!(
+                "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
+                format_ass_time(cue.start),
+                format_ass_time(cue.end),
+                escape_ass(&cue.text.to_uppercase())
+            ));
+            continue;
+        }
+
+        for (active, word) in cue.words.iter().enumerate() {
+            // Hold each highlight until the next word starts so the line never blinks
+            let end = cue
+                .words
+                .get(active + 1)
+                .map(|next| next.start)
+                .unwrap_or(cue.end);
+            if end <= word.start {
+                continue;
+            }
+
+            let text = cue
+                .words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    let word_text = escape_ass(&w.word.to_uppercase());
+                    if i == active {
+                        format!("{{\\c&H0000FFFF&\\fscx110\\fscy110}}{}{{\\r}}", word_text)
+                    } else {
+                        word_text
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            ass.push_str(&format!(
+                "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
+                format_ass_time(word.start),
+                format_ass_time(end),
+                text
+            ));
+        }
+    }
+
+    ass
+}
+
+// Groups words into segments of 3-6 words for TikTok-style captions
+fn segment_words(words: &[WordTiming]) -> Vec<&[WordTiming]> {
+    let mut segments: Vec<&[WordTiming]> = Vec::new();
+    let mut segment_start = 0;
+
+    for (i, word) in words.iter().enumerate() {
+        let segment_len = i + 1 - segment_start;
+
+        // Create shorter segments for better readability
+        if (segment_len >= 4 && word.word.ends_with(['.', '!', '?', ','])) || segment_len >= 6 {
+            segments.push(&words[segment_start..=i]);
+            segment_start = i + 1;
+        }
+    }
+
+    // Add remaining words
+    if segment_start < words.len() {
+        segments.push(&words[segment_start..]);
+    }
+
+    segments
+}
+
+// Spreads the words of `text` over [start, end], giving longer words more time
+pub fn distribute_words(text: &str, start: f64, end: f64) -> Vec<WordTiming> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let total_weight: usize = words.iter().map(|w| w.chars().count() + 1).sum();
+
+    if total_weight == 0 || end <= start {
+        return Vec::new();
     }
 
-    let caption_text = format!("Generated {} caption segments", segments.len());
-    Ok((srt_content, caption_text))
+    let seconds_per_unit = (end - start) / total_weight as f64;
+    let mut cursor = start;
+
+    words
+        .into_iter()
+        .map(|word| {
+            let word_end = cursor + (word.chars().count() + 1) as f64 * seconds_per_unit;
+            let timing = WordTiming {
+                word: word.to_string(),
+                start: cursor,
+                end: word_end.min(end),
+            };
+            cursor = word_end;
+            timing
+        })
+        .collect()
+}
+
+// Times the words of `text` against the speech in `audio_path`: pauses found by
+// ffmpeg's silencedetect are snapped to sentence boundaries, and words are
+// spread only over the speech between pauses.
+pub async fn align_with_silence(
+    audio_path: &str,
+    text: &str,
+    duration: f64,
+) -> Result<Vec<WordTiming>> {
+    let regions = detect_speech_regions(audio_path, duration).await?;
+    if regions.is_empty() {
+        return Err(anyhow!("No speech detected in {}", audio_path));
+    }
+
+    Ok(align_to_regions(text, &regions))
+}
+
+// The text-side half of `align_with_silence`, given the speech regions
+fn align_to_regions(text: &str, regions: &[(f64, f64)]) -> Vec<WordTiming> {
+    let sentences = split_sentences(text);
+    let total_chars: usize = sentences.iter().map(|s| s.chars().count()).sum();
+    let total_speech: f64 = regions.iter().map(|(start, end)| end - start).sum();
+
+    // Where each sentence boundary falls, as a fraction of the text
+    let mut boundaries = Vec::new();
+    let mut chars = 0;
+    for sentence in &sentences[..sentences.len().saturating_sub(1)] {
+        chars += sentence.chars().count();
+        boundaries.push(chars as f64 / total_chars.max(1) as f64);
+    }
+
+    // Pair pauses with the nearest unused later sentence boundary; each pair
+    // becomes a sync point that both the text and the audio are cut at
+    let mut sync_points = Vec::new();
+    let mut speech = 0.0;
+    let mut next_boundary = 0;
+    for (gap, (start, end)) in regions[..regions.len() - 1].iter().enumerate() {
+        speech += end - start;
+        let position = speech / total_speech;
+
+        let nearest = (next_boundary..boundaries.len()).min_by(|&a, &b| {
+            (boundaries[a] - position)
+                .abs()
+                .total_cmp(&(boundaries[b] - position).abs())
+        });
+
+        if let Some(boundary) = nearest {
+            if (boundaries[boundary] - position).abs() <= SENTENCE_SNAP_TOLERANCE {
+                sync_points.push((gap + 1, boundary + 1));
+                next_boundary = boundary + 1;
+            }
+        }
+    }
+    sync_points.push((regions.len(), sentences.len()));
+
+    let mut timings = Vec::new();
+    let (mut region_start, mut sentence_start) = (0, 0);
+    for (region_end, sentence_end) in sync_points {
+        let chunk = sentences[sentence_start..sentence_end].join(" ");
+        timings.extend(distribute_over_regions(&chunk, &regions[region_start..region_end]));
+        region_start = region_end;
+        sentence_start = sentence_end;
+    }
+
+    timings
+}
+
+// Speech regions are the gaps between the silences ffmpeg reports
+async fn detect_speech_regions(audio_path: &str, duration: f64) -> Result<Vec<(f64, f64)>> {
+    let filter = format!(
+        "silencedetect=noise={}dB:d={}",
+        SILENCE_NOISE_DB, SILENCE_MIN_SECONDS
+    );
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i", audio_path, "-af", &filter, "-f", "null", "-"])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Silence detection failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(parse_speech_regions(&String::from_utf8_lossy(&output.stderr), duration))
+}
+
+fn parse_speech_regions(log: &str, duration: f64) -> Vec<(f64, f64)> {
+    let mut regions = Vec::new();
+    let mut speech_start = Some(0.0);
+
+    for line in log.lines() {
+        if let Some(value) = parse_silencedetect_value(line, "silence_start:") {
+            if let Some(start) = speech_start.take() {
+                regions.push((start, value.min(duration)));
+            }
+        } else if let Some(value) = parse_silencedetect_value(line, "silence_end:") {
+            speech_start = Some(value);
+        }
+    }
+
+    if let Some(start) = speech_start {
+        regions.push((start, duration));
+    }
+
+    // Drop clicks and other blips too short to hold a word
+    regions.retain(|(start, end)| end - start >= 0.1);
+    regions
+}
+
+fn parse_silencedetect_value(line: &str, key: &str) -> Option<f64> {
+    let rest = &line[line.find(key)? + key.len()..];
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+// Like `distribute_words`, but the clock only runs inside the given regions
+fn distribute_over_regions(text: &str, regions: &[(f64, f64)]) -> Vec<WordTiming> {
+    let speech: f64 = regions.iter().map(|(start, end)| end - start).sum();
+    let to_real_time = |offset: f64| {
+        let mut remaining = offset;
+        for (start, end) in regions {
+            if remaining <= end - start {
+                return start + remaining;
+            }
+            remaining -= end - start;
+        }
+        regions.last().map(|(_, end)| *end).unwrap_or(0.0)
+    };
+
+    distribute_words(text, 0.0, speech)
+        .into_iter()
+        .map(|word| WordTiming {
+            start: to_real_time(word.start),
+            end: to_real_time(word.end),
+            word: word.word,
+        })
+        .collect()
+}
+
+// Splits on sentence-ending punctuation, keeping the punctuation
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+
+    for word in text.split_whitespace() {
+        current.push(word);
+        let trimmed = word.trim_end_matches(['"', '\'', ')', '”', '’']);
+        if trimmed.ends_with(['.', '!', '?']) {
+            sentences.push(current.join(" "));
+            current.clear();
+        }
+    }
+
+    if !current.is_empty() {
+        sentences.push(current.join(" "));
+    }
+
+    sentences
 }
 
 fn format_time(seconds: f64) -> String {
@@ -71,13 +489,134 @@ fn format_time(seconds: f64) -> String {
     format!("{:02}:{:02}:{:02},{:03}", hours, minutes, secs, millis)
 }
 
-pub async fn save_srt_file(srt_content: &str) -> Result<String> {
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+// ASS uses H:MM:SS.cc
+fn format_ass_time(seconds: f64) -> String {
+    let centis = (seconds.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        (centis / 6000) % 60,
+        (centis / 100) % 60,
+        centis % 100
+    )
+}
+
+// Braces and backslashes would be read as override tags
+fn escape_ass(text: &str) -> String {
+    text.replace('\\', "").replace('{', "(").replace('}', ")")
+}
+
+// Writes the cues in every caption format as `uploads/<stem>.<ext>`, e.g.
+// `<workspace>/captions.srt`, `<workspace>/captions.vtt`, ...
+pub async fn save_caption_files(
+    cues: &[CaptionCue],
+    aspect_ratio: &str,
+    style: CaptionStyle,
+    font_size: u32,
+    stem: &str,
+) -> Result<Vec<CaptionFile>> {
+    let mut files = Vec::new();
+
+    for format in CaptionFormat::ALL {
+        let content = match format {
+            CaptionFormat::Srt => to_srt(cues),
+            CaptionFormat::Vtt => to_vtt(cues),
+            CaptionFormat::Ass => to_ass(cues, aspect_ratio, style == CaptionStyle::Karaoke, font_size),
+            CaptionFormat::Json => to_json(cues)?,
+        };
+
+        let filename = format!("{}.{}", stem, format.extension());
+        std::fs::write(upload_path(&filename), content)?;
+        files.push(CaptionFile { format, filename });
+    }
+
+    Ok(files)
+}
+
+// Word timings are stored next to the audio they describe, e.g.
+// `<workspace>/narration.mp3` -> `<workspace>/narration.words.json`
+pub fn word_timings_filename(audio_filename: &str) -> String {
+    let stem = audio_filename
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(audio_filename);
+    format!("{}.words.json", stem)
+}
+
+fn word_timings_path(audio_filename: &str) -> String {
+    upload_path(&word_timings_filename(audio_filename))
+}
+
+pub fn save_word_timings(audio_filename: &str, words: &[WordTiming]) -> Result<()> {
+    std::fs::write(word_timings_path(audio_filename), serde_json::to_string(words)?)?;
+    Ok(())
+}
+
+pub fn remove_word_timings(audio_filename: &str) {
+    let _ = std::fs::remove_file(word_timings_path(audio_filename));
+}
+
+pub fn load_word_timings(audio_filename: &str) -> Option<Vec<WordTiming>> {
+    read_word_timings(Path::new(&word_timings_path(audio_filename)))
+}
+
+// Timings from a `.words.json` file anywhere on disk
+pub fn read_word_timings(path: &Path) -> Option<Vec<WordTiming>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let srt_filename = format!("captions_{}.srt", timestamp);
-    let srt_path = format!("uploads/{}", srt_filename);
+    fn find<'a>(words: &'a [WordTiming], word: &str) -> &'a WordTiming {
+        words.iter().find(|w| w.word == word).unwrap()
+    }
+
+    #[test]
+    fn parse_speech_regions_reads_the_gaps_between_silences() {
+        let log = "\
+[silencedetect @ 0x1] silence_start: 2.0
+[silencedetect @ 0x1] silence_end: 2.5 | silence_duration: 0.5
+[silencedetect @ 0x1] silence_start: 4.0
+[silencedetect @ 0x1] silence_end: 4.05 | silence_duration: 0.05
+[silencedetect @ 0x1] silence_start: 4.1
+[silencedetect @ 0x1] silence_end: 5.0 | silence_duration: 0.9
+";
+        // The 50ms blip between 4.05 and 4.1 is dropped
+        assert_eq!(parse_speech_regions(log, 8.0), vec![(0.0, 2.0), (2.5, 4.0), (5.0, 8.0)]);
+    }
+
+    #[test]
+    fn parse_speech_regions_ends_at_trailing_silence() {
+        let log = "\
+[silencedetect @ 0x1] silence_start: 3.0
+[silencedetect @ 0x1] silence_end: 3.5 | silence_duration: 0.5
+[silencedetect @ 0x1] silence_start: 7.0
+";
+        assert_eq!(parse_speech_regions(log, 8.0), vec![(0.0, 3.0), (3.5, 7.0)]);
+    }
 
-    std::fs::write(&srt_path, srt_content)?;
+    #[test]
+    fn align_to_regions_snaps_pauses_to_sentence_boundaries() {
+        let words = align_to_regions("One two. Three four.", &[(0.0, 2.0), (3.0, 5.0)]);
 
-    Ok(srt_filename)
+        assert_eq!(words.len(), 4);
+        assert_eq!(find(&words, "One").start, 0.0);
+        assert_eq!(find(&words, "two.").end, 2.0);
+        assert_eq!(find(&words, "Three").start, 3.0);
+        assert_eq!(find(&words, "four.").end, 5.0);
+    }
+
+    #[test]
+    fn align_to_regions_ignores_pauses_far_from_a_boundary() {
+        let words = align_to_regions("One two three four. Five.", &[(0.0, 1.0), (1.5, 5.0)]);
+
+        // The pause comes too early to be the end of the first sentence, so the
+        // text runs through it and no word starts inside it
+        assert!(find(&words, "four.").end > 1.5);
+        assert!(words.iter().all(|w| w.start <= 1.0 || w.start >= 1.5));
+        assert_eq!(words.last().unwrap().end, 5.0);
+    }
 }
@@ -0,0 +1,206 @@
+use crate::captions::WordTiming;
+use anyhow::Result;
+
+// How long a card stays up when the narration does not start with its title
+const DEFAULT_CARD_SECONDS: f64 = 3.0;
+// Keep the card up briefly after the last title word
+const CARD_TAIL_SECONDS: f64 = 0.3;
+// Longer titles and comments are cut off with an ellipsis
+const MAX_BODY_LINES: usize = 5;
+
+#[derive(Clone)]
+pub struct PostMeta {
+    pub title: String,
+    pub subreddit: String,
+    pub author: String,
+    pub score: i64,
+}
+
+// A Reddit-style card drawn over the background between `start` and `end`
+#[derive(Clone)]
+pub struct Card {
+    pub start: f64,
+    pub end: f64,
+    pub header: String,
+    pub body: String,
+    pub footer: String,
+}
+
+struct CardLayout {
+    box_x: u32,
+    box_y: u32,
+    box_w: u32,
+    padding: u32,
+    header_size: u32,
+    body_size: u32,
+    footer_size: u32,
+}
+
+impl CardLayout {
+    fn for_aspect(aspect_ratio: &str) -> Self {
+        if aspect_ratio == "9:16" {
+            // Upper middle of the frame, clear of the captions at the bottom
+            CardLayout {
+                box_x: 60,
+                box_y: 480,
+                box_w: 960,
+                padding: 44,
+                header_size: 40,
+                body_size: 60,
+                footer_size: 40,
+            }
+        } else {
+            CardLayout {
+                box_x: 260,
+                box_y: 160,
+                box_w: 1400,
+                padding: 50,
+                header_size: 38,
+                body_size: 58,
+                footer_size: 38,
+            }
+        }
+    }
+}
+
+// The title card is shown while the title is being read, which the word
+// timings tell us when the narration starts with the title
+pub fn title_card(post: &PostMeta, words: &[WordTiming], duration: f64) -> Card {
+    let title_words: Vec<String> = post.title.split_whitespace().map(normalize_word).collect();
+    let narrated: Vec<String> = words.iter().map(|w| normalize_word(&w.word)).collect();
+
+    let end = if !title_words.is_empty() && narrated.starts_with(&title_words) {
+        words[title_words.len() - 1].end + CARD_TAIL_SECONDS
+    } else {
+        DEFAULT_CARD_SECONDS
+    };
+
+    post_card(post, 0.0, end.min(duration))
+}
+
+pub fn post_card(post: &PostMeta, start: f64, end: f64) -> Card {
+    Card {
+        start,
+        end,
+        header: format!("r/{}  •  u/{}", post.subreddit, post.author),
+        body: post.title.clone(),
+        footer: format!("▲ {}", format_score(post.score)),
+    }
+}
+
+pub fn comment_card(author: &str, body: &str, score: i64, start: f64, end: f64) -> Card {
+    Card {
+        start,
+        end,
+        header: format!("u/{}", author),
+        body: body.to_string(),
+        footer: format!("▲ {}", format_score(score)),
+    }
+}
+
+// Builds the drawbox/drawtext chain for `cards`, to be appended to a video
+// filter. Card text goes through files written next to `file_prefix` so it
+// never needs filter escaping; they go away with the render's workspace.
+pub fn card_filters(cards: &[Card], aspect_ratio: &str, file_prefix: &str) -> Result<String> {
+    let layout = CardLayout::for_aspect(aspect_ratio);
+    let mut filters = String::new();
+
+    for (i, card) in cards.iter().enumerate() {
+        if card.end <= card.start {
+            continue;
+        }
+
+        let enable = format!("enable='between(t,{:.3},{:.3})'", card.start, card.end);
+
+        // drawtext does not wrap, so break the body into lines that fit the box
+        let chars_per_line =
+            ((layout.box_w - 2 * layout.padding) as f32 / (layout.body_size as f32 * 0.52)) as usize;
+        let body_lines = wrap_text(&card.body, chars_per_line, MAX_BODY_LINES);
+
+        let line_spacing = layout.body_size / 4;
+        let header_y = layout.box_y + layout.padding;
+        let body_y = header_y + layout.header_size + layout.padding / 2;
+        let body_h = body_lines.len() as u32 * (layout.body_size + line_spacing);
+        let footer_y = body_y + body_h + layout.padding / 2;
+        let box_h = footer_y + layout.footer_size + layout.padding - layout.box_y;
+
+        filters.push_str(&format!(
+            ",drawbox=x={}:y={}:w={}:h={}:color=white@0.95:t=fill:{}",
+            layout.box_x, layout.box_y, layout.box_w, box_h, enable
+        ));
+        // Reddit orange accent along the top edge
+        filters.push_str(&format!(
+            ",drawbox=x={}:y={}:w={}:h=10:color=0xFF4500:t=fill:{}",
+            layout.box_x, layout.box_y, layout.box_w, enable
+        ));
+
+        let text_x = layout.box_x + layout.padding;
+        let texts = [
+            ("header", card.header.clone(), header_y, layout.header_size, "0x787C7E", 0),
+            ("body", body_lines.join("\n"), body_y, layout.body_size, "0x1A1A1B", line_spacing),
+            ("footer", card.footer.clone(), footer_y, layout.footer_size, "0xFF4500", 0),
+        ];
+
+        for (part, text, y, size, color, spacing) in texts {
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let path = format!("{}_card{}_{}.txt", file_prefix, i, part);
+            std::fs::write(&path, text)?;
+            let path_escaped = path.replace('\\', "\\\\").replace(':', "\\:");
+
+            filters.push_str(&format!(
+                ",drawtext=textfile='{}':expansion=none:x={}:y={}:fontsize={}:fontcolor={}:line_spacing={}:{}",
+                path_escaped, text_x, y, size, color, spacing, enable
+            ));
+        }
+    }
+
+    Ok(filters)
+}
+
+fn wrap_text(text: &str, chars_per_line: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > chars_per_line {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+
+    lines
+}
+
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+// 1234 -> 1.2k, 56789 -> 56.8k
+fn format_score(score: i64) -> String {
+    if score.abs() >= 1_000_000 {
+        format!("{:.1}m", score as f64 / 1_000_000.0)
+    } else if score.abs() >= 1_000 {
+        format!("{:.1}k", score as f64 / 1_000.0)
+    } else {
+        score.to_string()
+    }
+}
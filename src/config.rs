@@ -0,0 +1,324 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::captions::{DEFAULT_CAPTION_FONT_SIZE, DEFAULT_CAPTION_FONT_SIZE_VERTICAL};
+use crate::tts::{DEFAULT_BASE_WPM, DEFAULT_PIPER_BINARY, DEFAULT_PIPER_MODEL_DIR};
+use crate::video::{OutputSettings, DEFAULT_RESOLUTION, DEFAULT_X264_PRESET, X264_PRESETS};
+use crate::workspace::DEFAULT_UPLOAD_DIR;
+
+// Read when RVM_CONFIG does not name another file; optional
+pub const DEFAULT_CONFIG_FILE: &str = "rvm.toml";
+
+// Server and pipeline settings. Every value has a default, can be set in the
+// TOML file, and can be overridden by an `RVM_<SECTION>_<KEY>` variable, e.g.
+// RVM_SERVER_BIND or RVM_VIDEO_X264_PRESET.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tts: TtsConfig,
+    pub video: VideoConfig,
+    pub retention: RetentionConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    // Videos rendered at the same time; further jobs wait in the queue
+    pub workers: usize,
+    pub upload_dir: PathBuf,
+    pub music_dir: PathBuf,
+    pub background_dir: PathBuf,
+    pub reddit_base_url: String,
+    // Bearer token for /admin routes, which are refused while it is empty
+    pub admin_token: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "0.0.0.0:8080".to_string(),
+            workers: 2,
+            upload_dir: PathBuf::from(DEFAULT_UPLOAD_DIR),
+            music_dir: PathBuf::from("music"),
+            background_dir: PathBuf::from("backgrounds"),
+            reddit_base_url: "https://www.reddit.com".to_string(),
+            admin_token: String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsConfig {
+    pub base_wpm: u32,
+    pub piper_model_dir: PathBuf,
+    pub piper_binary: String,
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        TtsConfig {
+            base_wpm: DEFAULT_BASE_WPM,
+            piper_model_dir: PathBuf::from(DEFAULT_PIPER_MODEL_DIR),
+            piper_binary: DEFAULT_PIPER_BINARY.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    // Short side of the frame, e.g. 720 or 1080
+    pub resolution: u32,
+    pub x264_preset: String,
+    pub caption_font_size: u32,
+    pub caption_font_size_vertical: u32,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            resolution: DEFAULT_RESOLUTION,
+            x264_preset: DEFAULT_X264_PRESET.to_string(),
+            caption_font_size: DEFAULT_CAPTION_FONT_SIZE,
+            caption_font_size_vertical: DEFAULT_CAPTION_FONT_SIZE_VERTICAL,
+        }
+    }
+}
+
+impl VideoConfig {
+    pub fn output_settings(&self) -> OutputSettings {
+        OutputSettings {
+            resolution: self.resolution,
+            x264_preset: self.x264_preset.clone(),
+            caption_font_size: self.caption_font_size,
+            caption_font_size_vertical: self.caption_font_size_vertical,
+        }
+    }
+}
+
+// A limit of 0 turns it off
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub max_age_hours: u64,
+    pub max_total_bytes: u64,
+    // The newest items survive both limits
+    pub keep_last: usize,
+    pub sweep_minutes: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_age_hours: 72,
+            max_total_bytes: 0,
+            keep_last: 20,
+            sweep_minutes: 10,
+        }
+    }
+}
+
+impl Config {
+    // Reads the file named by RVM_CONFIG, or rvm.toml when it exists, then
+    // applies environment overrides and validates the result
+    pub fn load() -> Result<Self> {
+        let mut config = match std::env::var("RVM_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            Err(_) => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| anyhow!("Invalid {}: {}", path.display(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        override_var("RVM_SERVER_BIND", &mut self.server.bind)?;
+        override_var("RVM_SERVER_WORKERS", &mut self.server.workers)?;
+        override_var("RVM_SERVER_UPLOAD_DIR", &mut self.server.upload_dir)?;
+        override_var("RVM_SERVER_MUSIC_DIR", &mut self.server.music_dir)?;
+        override_var("RVM_SERVER_BACKGROUND_DIR", &mut self.server.background_dir)?;
+        override_var("RVM_SERVER_REDDIT_BASE_URL", &mut self.server.reddit_base_url)?;
+        override_var("RVM_SERVER_ADMIN_TOKEN", &mut self.server.admin_token)?;
+
+        override_var("RVM_TTS_BASE_WPM", &mut self.tts.base_wpm)?;
+        override_var("RVM_TTS_PIPER_MODEL_DIR", &mut self.tts.piper_model_dir)?;
+        override_var("RVM_TTS_PIPER_BINARY", &mut self.tts.piper_binary)?;
+
+        override_var("RVM_VIDEO_RESOLUTION", &mut self.video.resolution)?;
+        override_var("RVM_VIDEO_X264_PRESET", &mut self.video.x264_preset)?;
+        override_var("RVM_VIDEO_CAPTION_FONT_SIZE", &mut self.video.caption_font_size)?;
+        override_var(
+            "RVM_VIDEO_CAPTION_FONT_SIZE_VERTICAL",
+            &mut self.video.caption_font_size_vertical,
+        )?;
+
+        override_var("RVM_RETENTION_MAX_AGE_HOURS", &mut self.retention.max_age_hours)?;
+        override_var("RVM_RETENTION_MAX_TOTAL_BYTES", &mut self.retention.max_total_bytes)?;
+        override_var("RVM_RETENTION_KEEP_LAST", &mut self.retention.keep_last)?;
+        override_var("RVM_RETENTION_SWEEP_MINUTES", &mut self.retention.sweep_minutes)?;
+
+        Ok(())
+    }
+
+    // Reports every problem at once rather than one per restart
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if !self
+            .server
+            .bind
+            .to_socket_addrs()
+            .is_ok_and(|mut addrs| addrs.next().is_some())
+        {
+            problems.push(format!(
+                "server.bind must be a host:port address, got \"{}\"",
+                self.server.bind
+            ));
+        }
+        if self.server.workers == 0 {
+            problems.push("server.workers must be at least 1".to_string());
+        }
+        if self.server.upload_dir.as_os_str().is_empty() {
+            problems.push("server.upload_dir must not be empty".to_string());
+        }
+        if !self.server.reddit_base_url.starts_with("http://")
+            && !self.server.reddit_base_url.starts_with("https://")
+        {
+            problems.push(format!(
+                "server.reddit_base_url must start with http:// or https://, got \"{}\"",
+                self.server.reddit_base_url
+            ));
+        }
+
+        if !(80..=450).contains(&self.tts.base_wpm) {
+            problems.push(format!(
+                "tts.base_wpm must be between 80 and 450, got {}",
+                self.tts.base_wpm
+            ));
+        }
+
+        if !(240..=2160).contains(&self.video.resolution) || !self.video.resolution.is_multiple_of(2) {
+            problems.push(format!(
+                "video.resolution must be an even number between 240 and 2160, got {}",
+                self.video.resolution
+            ));
+        }
+        if !X264_PRESETS.contains(&self.video.x264_preset.as_str()) {
+            problems.push(format!(
+                "video.x264_preset must be one of {}, got \"{}\"",
+                X264_PRESETS.join(", "),
+                self.video.x264_preset
+            ));
+        }
+        for (name, size) in [
+            ("video.caption_font_size", self.video.caption_font_size),
+            ("video.caption_font_size_vertical", self.video.caption_font_size_vertical),
+        ] {
+            if !(8..=200).contains(&size) {
+                problems.push(format!("{} must be between 8 and 200, got {}", name, size));
+            }
+        }
+
+        if self.retention.sweep_minutes == 0 {
+            problems.push("retention.sweep_minutes must be at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid configuration:\n  - {}", problems.join("\n  - ")))
+        }
+    }
+}
+
+// Sets `target` from the environment variable `name` when it is set
+fn override_var<T>(name: &str, target: &mut T) -> Result<()>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Ok(value) = std::env::var(name) else {
+        return Ok(());
+    };
+
+    *target = value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid {}=\"{}\": {}", name, value, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut config = Config::default();
+        config.server.bind = "nowhere".to_string();
+        config.server.workers = 0;
+        config.server.reddit_base_url = "www.reddit.com".to_string();
+        config.tts.base_wpm = 20;
+        config.video.resolution = 721;
+        config.video.x264_preset = "ludicrous".to_string();
+        config.video.caption_font_size_vertical = 4;
+        config.retention.sweep_minutes = 0;
+
+        let error = config.validate().unwrap_err().to_string();
+        for key in [
+            "server.bind",
+            "server.workers",
+            "server.reddit_base_url",
+            "tts.base_wpm",
+            "video.resolution",
+            "video.x264_preset",
+            "video.caption_font_size_vertical",
+            "retention.sweep_minutes",
+        ] {
+            assert!(error.contains(key), "{} missing from: {}", key, error);
+        }
+        assert!(!error.contains("video.caption_font_size "));
+    }
+
+    #[test]
+    fn validate_accepts_values_at_the_limits() {
+        let mut config = Config::default();
+        config.server.bind = "127.0.0.1:0".to_string();
+        config.tts.base_wpm = 450;
+        config.video.resolution = 240;
+        config.video.x264_preset = "veryslow".to_string();
+        config.video.caption_font_size = 8;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn file_settings_fill_in_defaults_and_reject_unknown_keys() {
+        let config: Config = toml::from_str("[video]\nresolution = 720\n").unwrap();
+        assert_eq!(config.video.resolution, 720);
+        assert_eq!(config.video.x264_preset, DEFAULT_X264_PRESET);
+        assert_eq!(config.server.workers, 2);
+
+        assert!(toml::from_str::<Config>("[video]\nresolutoin = 720\n").is_err());
+    }
+}
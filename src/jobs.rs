@@ -0,0 +1,463 @@
+use actix_web::web::Bytes;
+use anyhow::{anyhow, Result};
+use futures_util::Stream;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+
+use reddit_video_maker::captions::{CaptionFile, CaptionFormat};
+use reddit_video_maker::tts::TtsRegistry;
+use reddit_video_maker::video::{RenderEvent, RenderStage};
+use reddit_video_maker::workspace::upload_dir;
+use reddit_video_maker::{render, RenderRequest};
+
+use crate::{ErrorResponse, VideoPartResponse, VideoResponse};
+
+// Events buffered per job for slow SSE subscribers before they start lagging
+const EVENT_BUFFER: usize = 64;
+
+// How long a failed job, which has no output to outlive, stays queryable
+const FAILED_JOB_TTL: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Serialize)]
+pub struct JobStatus {
+    id: String,
+    status: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<RenderStage>,
+    progress: f64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    result: Option<VideoResponse>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+#[derive(Clone)]
+pub enum JobEvent {
+    Status(Box<JobStatus>),
+    Stage(RenderStage),
+    Progress(f64),
+}
+
+impl JobEvent {
+    fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            JobEvent::Status(status)
+                if matches!(status.status, JobState::Succeeded | JobState::Failed)
+        )
+    }
+
+    fn to_sse(&self) -> String {
+        let (name, data) = match self {
+            JobEvent::Status(status) => ("status", serde_json::to_string(status)),
+            JobEvent::Stage(stage) => (
+                "stage",
+                serde_json::to_string(&serde_json::json!({ "stage": stage })),
+            ),
+            JobEvent::Progress(percent) => (
+                "progress",
+                serde_json::to_string(&serde_json::json!({ "percent": percent })),
+            ),
+        };
+
+        format!("event: {}\ndata: {}\n\n", name, data.unwrap_or_default())
+    }
+}
+
+struct JobEntry {
+    status: JobStatus,
+    events: broadcast::Sender<JobEvent>,
+    // Directory under uploads/ holding the job's output, once it succeeded
+    workspace: Option<String>,
+    // Videos and captions the job wrote to uploads/
+    files: Vec<String>,
+    // When the job succeeded or failed
+    finished_at: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchState {
+    Queued,
+    Running,
+    // Every job has succeeded or failed
+    Finished,
+}
+
+#[derive(Serialize)]
+pub struct BatchStatus {
+    id: String,
+    status: BatchState,
+    total: usize,
+    queued: usize,
+    running: usize,
+    succeeded: usize,
+    failed: usize,
+    // Average over all jobs
+    progress: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download: Option<String>,
+    // In the order the entries were submitted
+    jobs: Vec<JobStatus>,
+}
+
+impl BatchStatus {
+    pub fn is_finished(&self) -> bool {
+        self.status == BatchState::Finished
+    }
+}
+
+type JobReceiver = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<(String, RenderRequest)>>>;
+
+#[derive(Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<String, JobEntry>>>,
+    // Batch id to the ids of its jobs
+    batches: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    sender: mpsc::UnboundedSender<(String, RenderRequest)>,
+}
+
+impl JobQueue {
+    // Spawns a fixed number of workers that pull jobs off a shared channel,
+    // so at most `workers` ffmpeg renders run at the same time.
+    pub fn start(workers: usize, registry: Arc<TtsRegistry>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let receiver: JobReceiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+        let queue = JobQueue {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            batches: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+        };
+
+        for worker_id in 0..workers.max(1) {
+            tokio::spawn(run_worker(
+                worker_id,
+                queue.clone(),
+                receiver.clone(),
+                registry.clone(),
+            ));
+        }
+
+        queue
+    }
+
+    pub fn enqueue(&self, job: RenderRequest) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+
+        self.jobs.lock().unwrap().insert(
+            id.clone(),
+            JobEntry {
+                status: JobStatus {
+                    id: id.clone(),
+                    status: JobState::Queued,
+                    stage: None,
+                    progress: 0.0,
+                    result: None,
+                    error: None,
+                },
+                events,
+                workspace: None,
+                files: Vec::new(),
+                finished_at: None,
+            },
+        );
+
+        if self.sender.send((id.clone(), job)).is_err() {
+            self.jobs.lock().unwrap().remove(&id);
+            return Err(anyhow!("Job queue is not running"));
+        }
+
+        Ok(id)
+    }
+
+    // Enqueues one job per request and groups them under a batch id
+    pub fn enqueue_batch(&self, jobs: Vec<RenderRequest>) -> Result<String> {
+        let mut job_ids = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            job_ids.push(self.enqueue(job)?);
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        self.batches.lock().unwrap().insert(id.clone(), job_ids);
+        Ok(id)
+    }
+
+    pub fn batch_status(&self, id: &str) -> Option<BatchStatus> {
+        let job_ids = self.batches.lock().unwrap().get(id)?.clone();
+        let jobs: Vec<JobStatus> = job_ids.iter().filter_map(|job_id| self.status(job_id)).collect();
+        let count = |state: JobState| jobs.iter().filter(|job| job.status == state).count();
+
+        let (queued, running, succeeded, failed) = (
+            count(JobState::Queued),
+            count(JobState::Running),
+            count(JobState::Succeeded),
+            count(JobState::Failed),
+        );
+        let status = if queued == jobs.len() {
+            BatchState::Queued
+        } else if queued + running > 0 {
+            BatchState::Running
+        } else {
+            BatchState::Finished
+        };
+
+        Some(BatchStatus {
+            id: id.to_string(),
+            status,
+            total: jobs.len(),
+            queued,
+            running,
+            succeeded,
+            failed,
+            progress: jobs.iter().map(|job| job.progress).sum::<f64>() / jobs.len().max(1) as f64,
+            download: (status == BatchState::Finished && succeeded > 0)
+                .then(|| format!("/batch/{}/download", id)),
+            jobs,
+        })
+    }
+
+    // Output files of each finished job in the batch, numbered from 1 in
+    // submission order
+    pub fn batch_files(&self, id: &str) -> Option<Vec<(usize, Vec<String>)>> {
+        let job_ids = self.batches.lock().unwrap().get(id)?.clone();
+        let jobs = self.jobs.lock().unwrap();
+
+        Some(
+            job_ids
+                .iter()
+                .enumerate()
+                .filter_map(|(i, job_id)| {
+                    let job = jobs.get(job_id)?;
+                    (job.status.status == JobState::Succeeded).then(|| (i + 1, job.files.clone()))
+                })
+                .collect(),
+        )
+    }
+
+    pub fn status(&self, id: &str) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(id).map(|job| job.status.clone())
+    }
+
+    // Server-Sent Events for one job: the current status first, then stage and
+    // progress updates, ending once the job succeeds or fails.
+    pub fn event_stream(
+        &self,
+        id: &str,
+    ) -> Option<impl Stream<Item = Result<Bytes, actix_web::Error>>> {
+        let (snapshot, receiver) = {
+            let jobs = self.jobs.lock().unwrap();
+            let job = jobs.get(id)?;
+            (JobEvent::Status(Box::new(job.status.clone())), job.events.subscribe())
+        };
+
+        Some(futures_util::stream::unfold(
+            (Some(snapshot), receiver, false),
+            |(pending, mut receiver, finished)| async move {
+                if finished {
+                    return None;
+                }
+
+                let event = match pending {
+                    Some(event) => event,
+                    None => loop {
+                        match receiver.recv().await {
+                            Ok(event) => break event,
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        }
+                    },
+                };
+
+                let finished = event.is_terminal();
+                Some((
+                    Ok(Bytes::from(event.to_sse())),
+                    (None, receiver, finished),
+                ))
+            },
+        ))
+    }
+
+    fn update(
+        &self,
+        id: &str,
+        status: JobState,
+        result: Option<VideoResponse>,
+        error: Option<ErrorResponse>,
+    ) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.status.status = status;
+            job.status.result = result;
+            job.status.error = error;
+            if status == JobState::Succeeded {
+                job.status.progress = 100.0;
+            }
+            if matches!(status, JobState::Succeeded | JobState::Failed) {
+                job.finished_at = Some(Instant::now());
+            }
+            let _ = job.events.send(JobEvent::Status(Box::new(job.status.clone())));
+        }
+    }
+
+    // Workspace holding a finished job's files
+    pub fn workspace(&self, id: &str) -> Option<String> {
+        self.jobs.lock().unwrap().get(id)?.workspace.clone()
+    }
+
+    // Job ids by the workspace their output is in
+    pub fn jobs_by_workspace(&self) -> HashMap<String, String> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, job)| Some((job.workspace.clone()?, id.clone())))
+            .collect()
+    }
+
+    // Forgets finished jobs whose workspace is gone from uploads/, and failed
+    // jobs after FAILED_JOB_TTL, then batches with none of their jobs left.
+    // Returns how many jobs were dropped.
+    pub fn evict_finished(&self) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let before = jobs.len();
+
+        jobs.retain(|_, job| {
+            let Some(finished_at) = job.finished_at else {
+                return true;
+            };
+            match &job.workspace {
+                Some(workspace) => upload_dir().join(workspace).exists(),
+                None => finished_at.elapsed() < FAILED_JOB_TTL,
+            }
+        });
+
+        self.batches
+            .lock()
+            .unwrap()
+            .retain(|_, job_ids| job_ids.iter().any(|id| jobs.contains_key(id)));
+
+        before - jobs.len()
+    }
+
+    fn set_output(&self, id: &str, workspace: String, files: Vec<String>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.workspace = Some(workspace);
+            job.files = files;
+        }
+    }
+
+    fn report(&self, id: &str, event: RenderEvent) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            let event = match event {
+                RenderEvent::Stage(stage) => {
+                    job.status.stage = Some(stage);
+                    JobEvent::Stage(stage)
+                }
+                RenderEvent::Progress(percent) => {
+                    job.status.progress = percent;
+                    JobEvent::Progress(percent)
+                }
+            };
+            let _ = job.events.send(event);
+        }
+    }
+}
+
+async fn run_worker(
+    worker_id: usize,
+    queue: JobQueue,
+    receiver: JobReceiver,
+    registry: Arc<TtsRegistry>,
+) {
+    loop {
+        // Only hold the receiver lock while waiting, not while rendering
+        let next = receiver.lock().await.recv().await;
+        let Some((id, job)) = next else {
+            break;
+        };
+
+        log::info!("Worker {} picked up job {}", worker_id, id);
+        queue.update(&id, JobState::Running, None, None);
+
+        let aspect_ratio = job.aspect_ratio().to_string();
+        let reporter = |event: RenderEvent| queue.report(&id, event);
+        match render(&registry, job, &reporter).await {
+            Ok(output) => {
+                let download = |filename: &str| format!("/download/{}", filename);
+                let caption_urls = |files: &[CaptionFile]| -> BTreeMap<CaptionFormat, String> {
+                    files
+                        .iter()
+                        .map(|file| (file.format, download(&file.filename)))
+                        .collect()
+                };
+
+                // The top level fields describe the first part, so single
+                // videos look the same as before
+                let first = &output.parts[0];
+                let parts = if output.parts.len() > 1 {
+                    output
+                        .parts
+                        .iter()
+                        .enumerate()
+                        .map(|(i, part)| VideoPartResponse {
+                            part: i + 1,
+                            video: download(&part.video_filename),
+                            start: part.start,
+                            end: part.end,
+                            caption_files: caption_urls(&part.caption_files),
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+                let response = VideoResponse {
+                    video: download(&first.video_filename),
+                    aspect: aspect_ratio,
+                    captions: output.caption_text,
+                    srt_file: first
+                        .caption_files
+                        .iter()
+                        .find(|file| file.format == CaptionFormat::Srt)
+                        .map(|file| download(&file.filename)),
+                    caption_files: caption_urls(&first.caption_files),
+                    loudness: output.loudness,
+                    engines: output.engines,
+                    parts,
+                };
+                let files = output
+                    .parts
+                    .iter()
+                    .flat_map(|part| {
+                        std::iter::once(part.video_filename.clone())
+                            .chain(part.caption_files.iter().map(|file| file.filename.clone()))
+                    })
+                    .collect();
+                queue.set_output(&id, output.workspace_id, files);
+                queue.update(&id, JobState::Succeeded, Some(response), None);
+                log::info!("Job {} finished", id);
+            }
+            Err(e) => {
+                log::error!("Video creation failed for job {}: {}", id, e);
+                let error = ErrorResponse {
+                    error: format!("Video creation failed: {}", e),
+                    ffmpeg_error: Some(e.to_string()),
+                };
+                queue.update(&id, JobState::Failed, None, Some(error));
+            }
+        }
+    }
+}
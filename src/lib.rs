@@ -0,0 +1,16 @@
+// The rendering pipeline, shared by the web server and the `rvm` CLI
+pub mod backgrounds;
+pub mod captions;
+pub mod cards;
+pub mod config;
+pub mod loudness;
+pub mod media;
+pub mod music;
+pub mod reddit;
+pub mod render;
+pub mod story;
+pub mod tts;
+pub mod video;
+pub mod workspace;
+
+pub use render::{render, RenderOutput, RenderRequest, RenderRequestBuilder};
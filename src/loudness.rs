@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::process::Command;
+
+// Where YouTube, TikTok and Spotify normalize to
+pub const DEFAULT_TARGET_LUFS: f64 = -14.0;
+pub const DEFAULT_TRUE_PEAK: f64 = -1.5;
+// What ffmpeg's loudnorm accepts
+pub const MIN_TARGET_LUFS: f64 = -70.0;
+pub const MAX_TARGET_LUFS: f64 = -5.0;
+pub const MIN_TRUE_PEAK: f64 = -9.0;
+pub const MAX_TRUE_PEAK: f64 = 0.0;
+// Loudness range; narration is already fairly even
+const TARGET_LRA: f64 = 11.0;
+
+#[derive(Clone, Copy)]
+pub struct LoudnessTarget {
+    // Integrated loudness in LUFS
+    pub integrated: f64,
+    // Ceiling for inter-sample peaks in dBTP
+    pub true_peak: f64,
+}
+
+impl Default for LoudnessTarget {
+    fn default() -> Self {
+        LoudnessTarget {
+            integrated: DEFAULT_TARGET_LUFS,
+            true_peak: DEFAULT_TRUE_PEAK,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct LoudnessReport {
+    pub target_lufs: f64,
+    pub true_peak_ceiling: f64,
+    pub input_lufs: f64,
+    pub input_true_peak: f64,
+    pub output_lufs: f64,
+    pub output_true_peak: f64,
+}
+
+// What loudnorm's first pass measured, fed back into the second pass so it
+// can apply one linear gain instead of adjusting dynamically
+pub struct LoudnessMeasurement {
+    input_i: f64,
+    input_tp: f64,
+    input_lra: f64,
+    input_thresh: f64,
+    target_offset: f64,
+}
+
+impl LoudnessMeasurement {
+    // Silent audio measures as -inf, which loudnorm cannot take as input
+    fn is_usable(&self) -> bool {
+        [self.input_i, self.input_tp, self.input_lra, self.input_thresh, self.target_offset]
+            .iter()
+            .all(|value| value.is_finite())
+    }
+}
+
+// First pass: analyse the whole file without writing anything
+pub async fn measure_loudness(path: &str, target: LoudnessTarget) -> Result<LoudnessMeasurement> {
+    let output = Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-i",
+            path,
+            "-af",
+            &format!("{}:print_format=json", base_filter(target)),
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Loudness measurement failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let stats = parse_loudnorm_json(&String::from_utf8_lossy(&output.stderr))?;
+    Ok(LoudnessMeasurement {
+        input_i: stat(&stats, "input_i")?,
+        input_tp: stat(&stats, "input_tp")?,
+        input_lra: stat(&stats, "input_lra")?,
+        input_thresh: stat(&stats, "input_thresh")?,
+        target_offset: stat(&stats, "target_offset")?,
+    })
+}
+
+// Second pass filter, or None when the measurement cannot be used and the
+// audio should be left as it is
+pub fn normalize_filter(target: LoudnessTarget, measured: &LoudnessMeasurement) -> Option<String> {
+    if !measured.is_usable() {
+        return None;
+    }
+
+    Some(format!(
+        "{}:measured_I={:.2}:measured_TP={:.2}:measured_LRA={:.2}:measured_thresh={:.2}:offset={:.2}:linear=true:print_format=json",
+        base_filter(target),
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset
+    ))
+}
+
+// Combines the first pass measurement with the levels the second pass
+// printed to stderr
+pub fn loudness_report(
+    target: LoudnessTarget,
+    measured: &LoudnessMeasurement,
+    second_pass_stderr: &str,
+) -> Result<LoudnessReport> {
+    let stats = parse_loudnorm_json(second_pass_stderr)?;
+    Ok(LoudnessReport {
+        target_lufs: target.integrated,
+        true_peak_ceiling: target.true_peak,
+        input_lufs: measured.input_i,
+        input_true_peak: measured.input_tp,
+        output_lufs: stat(&stats, "output_i")?,
+        output_true_peak: stat(&stats, "output_tp")?,
+    })
+}
+
+fn base_filter(target: LoudnessTarget) -> String {
+    format!(
+        "loudnorm=I={:.1}:TP={:.1}:LRA={:.1}",
+        target.integrated, target.true_peak, TARGET_LRA
+    )
+}
+
+// loudnorm prints its stats as the last JSON object in ffmpeg's log
+fn parse_loudnorm_json(stderr: &str) -> Result<Value> {
+    let start = stderr
+        .rfind('{')
+        .ok_or_else(|| anyhow!("loudnorm did not report any statistics"))?;
+    let end = stderr[start..]
+        .find('}')
+        .map(|i| start + i + 1)
+        .ok_or_else(|| anyhow!("loudnorm statistics were cut off"))?;
+
+    Ok(serde_json::from_str(&stderr[start..end])?)
+}
+
+// Values are quoted strings and may be "-inf"
+fn stat(stats: &Value, key: &str) -> Result<f64> {
+    stats[key]
+        .as_str()
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .ok_or_else(|| anyhow!("loudnorm statistics are missing {}", key))
+}
@@ -1,50 +1,115 @@
-
 use actix_files as fs;
 use actix_multipart::Multipart;
-use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer, Result};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{middleware::Logger, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Result};
+use anyhow::anyhow;
 use futures_util::TryStreamExt as _;
+use minijinja::{context, Environment};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+mod batch;
+mod jobs;
+mod storage;
+
+use reddit_video_maker::backgrounds::{BackgroundLibrary, BackgroundSource};
+use reddit_video_maker::captions::{self, word_timings_filename, CaptionFormat};
+use reddit_video_maker::cards::PostMeta;
+use reddit_video_maker::config::{Config, RetentionConfig};
+use reddit_video_maker::loudness::{self, LoudnessTarget};
+use reddit_video_maker::music::{self, MusicLibrary, MusicOptions, MusicSource};
+use reddit_video_maker::reddit::{self, RedditClient};
+use reddit_video_maker::story::StorySegment;
+use reddit_video_maker::tts::*;
+use reddit_video_maker::video::safe_filename;
+use reddit_video_maker::workspace::{self, Workspace};
+use reddit_video_maker::render::check_range;
+use reddit_video_maker::RenderRequest;
+
+use batch::{parse_batch, write_zip};
+use jobs::JobQueue;
+use storage::StorageItem;
 
-mod tts;
-mod video;
-mod captions;
+const DEFAULT_COMMENT_LIMIT: usize = 10;
 
-use tts::*;
-use video::*;
+// Batch bodies hold whole stories, well past actix's 256 KiB default
+const BATCH_PAYLOAD_LIMIT: usize = 8 * 1024 * 1024;
+
+// Display names for the language picker; other languages show their code
+const LANGUAGE_NAMES: &[(&str, &str)] = &[("en", "English"), ("es", "Spanish")];
+
+// Read aloud by the /voices/{id}/sample endpoint
+const SAMPLE_TEXTS: &[(&str, &str)] = &[
+    ("en", "This is how I sound when I read your story."),
+    ("es", "Así sueno cuando leo tu historia."),
+];
 
 #[derive(Serialize)]
-struct Voice {
+struct CatalogueVoice {
     id: String,
     name: String,
+    engine: String,
+    locale: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gender: Option<String>,
+    sample_url: String,
 }
 
 #[derive(Serialize)]
-struct TtsVoices {
-    en: Vec<Voice>,
-    es: Vec<Voice>,
-}
-
-fn get_tts_voices() -> TtsVoices {
-    TtsVoices {
-        en: vec![
-            Voice { id: "com.au".to_string(), name: "Australian".to_string() },
-            Voice { id: "co.uk".to_string(), name: "British".to_string() },
-            Voice { id: "us".to_string(), name: "American".to_string() },
-            Voice { id: "ca".to_string(), name: "Canadian".to_string() },
-            Voice { id: "ind".to_string(), name: "Indian".to_string() },
-            Voice { id: "za".to_string(), name: "South African".to_string() },
-            Voice { id: "ie".to_string(), name: "Irish".to_string() },
-            Voice { id: "nz".to_string(), name: "New Zealand".to_string() },
-            Voice { id: "ng".to_string(), name: "Nigerian".to_string() },
-            Voice { id: "tt".to_string(), name: "Trinidad & Tobago".to_string() },
-        ],
-        es: vec![
-            Voice { id: "es".to_string(), name: "Spanish (Spain)".to_string() },
-            Voice { id: "mx".to_string(), name: "Mexican Spanish".to_string() },
-            Voice { id: "ar".to_string(), name: "Argentinian Spanish".to_string() },
-            Voice { id: "cl".to_string(), name: "Chilean Spanish".to_string() },
-        ],
+struct Language {
+    code: String,
+    name: String,
+}
+
+// Voices of every engine grouped by language. The page only offers engines
+// that let the user pick a voice.
+fn voice_catalogue(
+    registry: &TtsRegistry,
+    selectable_only: bool,
+) -> BTreeMap<String, Vec<CatalogueVoice>> {
+    let mut catalogue: BTreeMap<String, Vec<CatalogueVoice>> = BTreeMap::new();
+
+    for engine in registry.engines() {
+        if selectable_only && !engine.capabilities().voice_selection {
+            continue;
+        }
+        for voice in engine.voices() {
+            catalogue.entry(voice.lang).or_default().push(CatalogueVoice {
+                sample_url: format!("/voices/{}/sample", voice.id),
+                id: voice.id,
+                name: voice.name,
+                engine: engine.id().to_string(),
+                locale: voice.locale,
+                gender: voice.gender,
+            });
+        }
     }
+
+    catalogue
+}
+
+fn language_name(code: &str) -> String {
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(lang, _)| *lang == code)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| code.to_string())
+}
+
+fn templates() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_template("index.html", include_str!("../index/index.html"))
+        .expect("index/index.html is not a valid template");
+    env
+}
+
+#[derive(Serialize)]
+struct TtsEngineInfo {
+    id: String,
+    capabilities: EngineCapabilities,
+    voices: Vec<VoiceInfo>,
 }
 
 #[derive(Deserialize)]
@@ -53,22 +118,32 @@ struct TtsRequest {
     lang: Option<String>,
     voice: Option<String>,
     speed: Option<f32>,
+    engine: Option<String>,
 }
 
 #[derive(Serialize)]
 struct TtsResponse {
     audio: String,
     filename: String,
+    engine: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<Vec<captions::WordTiming>>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize)]
+struct RedditImportRequest {
+    url: String,
+    comments: Option<usize>,
+}
+
+#[derive(Serialize, Clone)]
 struct ErrorResponse {
     error: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     ffmpeg_error: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 struct VideoResponse {
     video: String,
     aspect: String,
@@ -76,28 +151,185 @@ struct VideoResponse {
     captions: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     srt_file: Option<String>,
+    // Download URL for every caption format that was written
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    caption_files: BTreeMap<CaptionFormat, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loudness: Option<loudness::LoudnessReport>,
+    // TTS engine of each narrated segment, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    engines: Vec<String>,
+    // Every part, in order, when the story was split
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parts: Vec<VideoPartResponse>,
+}
+
+#[derive(Serialize, Clone)]
+struct VideoPartResponse {
+    part: usize,
+    video: String,
+    // Span of the narration in this part, in seconds
+    start: f64,
+    end: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    caption_files: BTreeMap<CaptionFormat, String>,
+}
+
+#[derive(Serialize)]
+struct JobCreatedResponse {
+    job_id: String,
+    status: String,
+    status_url: String,
+}
+
+#[derive(Deserialize)]
+struct PurgeRequest {
+    older_than_hours: Option<f64>,
+    job_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StorageResponse {
+    total_bytes: u64,
+    policy: RetentionConfig,
+    items: Vec<StorageItem>,
+}
+
+#[derive(Serialize)]
+struct BatchCreatedResponse {
+    batch_id: String,
+    status: String,
+    status_url: String,
+    total: usize,
+}
+
+async fn index(
+    registry: web::Data<TtsRegistry>,
+    templates: web::Data<Environment<'static>>,
+) -> Result<HttpResponse> {
+    let voices = voice_catalogue(&registry, true);
+    let languages: Vec<Language> = voices
+        .keys()
+        .map(|code| Language {
+            code: code.clone(),
+            name: language_name(code),
+        })
+        .collect();
+
+    let page = templates.get_template("index.html").and_then(|template| {
+        template.render(context! {
+            voices,
+            languages,
+            default_lang => DEFAULT_LANG,
+            default_voice => DEFAULT_VOICE,
+        })
+    });
+
+    match page {
+        Ok(html) => Ok(HttpResponse::Ok().content_type("text/html").body(html)),
+        Err(e) => {
+            log::error!("Could not render the index page: {:#}", e);
+            Ok(HttpResponse::InternalServerError()
+                .content_type("text/plain")
+                .body("Could not render the page"))
+        }
+    }
 }
 
-async fn index() -> Result<HttpResponse> {
-    let html = include_str!("../index/index.html");
-    let _tts_voices = get_tts_voices();
+async fn voices(registry: web::Data<TtsRegistry>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(voice_catalogue(&registry, false)))
+}
 
-    // Simple template replacement for TTS voices
-    let html_with_voices = html.replace("{% for voice in tts_voices['en'] %}", "")
-        .replace("{{ voice.id }}", "us")
-        .replace("{{ voice.name }}", "American")
-        .replace("{% endfor %}", "");
+// A short phrase in the voice, synthesized the first time it is asked for
+// and served from uploads/voice-samples/ after that
+async fn voice_sample(
+    req: HttpRequest,
+    registry: web::Data<TtsRegistry>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let voice_id = path.into_inner();
+    let Some((engine, voice)) = registry.engines().find_map(|engine| {
+        engine
+            .voices()
+            .into_iter()
+            .find(|voice| voice.id == voice_id)
+            .map(|voice| (engine.id(), voice))
+    }) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: format!("Unknown voice: {}", voice_id),
+            ffmpeg_error: None,
+        }));
+    };
+
+    let safe_id: String = voice
+        .id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let sample_path = workspace::upload_dir()
+        .join(workspace::VOICE_SAMPLE_DIR)
+        .join(format!("{}-{}.mp3", engine, safe_id));
 
-    Ok(HttpResponse::Ok()
-        .content_type("text/html")
-        .body(html_with_voices))
+    if !sample_path.exists() {
+        if let Err(e) = generate_voice_sample(&registry, engine, &voice, &sample_path).await {
+            log::error!("Voice sample for {} failed: {}", voice_id, e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: format!("Voice sample failed: {}", e),
+                ffmpeg_error: None,
+            }));
+        }
+    }
+
+    Ok(fs::NamedFile::open(&sample_path)?.into_response(&req))
 }
 
-async fn generate_tts(req: web::Json<TtsRequest>) -> Result<HttpResponse> {
+async fn generate_voice_sample(
+    registry: &TtsRegistry,
+    engine: &str,
+    voice: &VoiceInfo,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let text = SAMPLE_TEXTS
+        .iter()
+        .find(|(lang, _)| *lang == voice.lang)
+        .unwrap_or(&SAMPLE_TEXTS[0])
+        .1;
+
+    let workspace = Workspace::create()?;
+    let output =
+        generate_tts_audio(registry, &workspace, text, &voice.lang, &voice.id, 1.0, Some(engine))
+            .await?;
+
+    // Moved into place whole, so a concurrent request never serves half a file
+    if let Some(dir) = target.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::rename(workspace::upload_path(&output.filename), target)?;
+    Ok(())
+}
+
+async fn tts_engines(registry: web::Data<TtsRegistry>) -> Result<HttpResponse> {
+    let engines: Vec<TtsEngineInfo> = registry
+        .engines()
+        .map(|engine| TtsEngineInfo {
+            id: engine.id().to_string(),
+            capabilities: engine.capabilities(),
+            voices: engine.voices(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(engines))
+}
+
+async fn generate_tts(
+    registry: web::Data<TtsRegistry>,
+    req: web::Json<TtsRequest>,
+) -> Result<HttpResponse> {
     let text = &req.text;
-    let lang = req.lang.as_deref().unwrap_or("en");
-    let voice = req.voice.as_deref().unwrap_or("us");
+    let lang = req.lang.as_deref().unwrap_or(DEFAULT_LANG);
+    let voice = req.voice.as_deref().unwrap_or(DEFAULT_VOICE);
     let speed = req.speed.unwrap_or(1.0);
+    let engine = req.engine.as_deref();
 
     if text.trim().is_empty() {
         return Ok(HttpResponse::BadRequest().json(ErrorResponse {
@@ -106,11 +338,43 @@ async fn generate_tts(req: web::Json<TtsRequest>) -> Result<HttpResponse> {
         }));
     }
 
-    match generate_tts_audio(text, lang, voice, speed).await {
-        Ok((_audio_path, filename)) => {
+    if let Err(e) = check_range("speed", speed, MIN_SPEED, MAX_SPEED) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+            error: e.to_string(),
+            ffmpeg_error: None,
+        }));
+    }
+
+    if let Some(id) = engine {
+        if id != "auto" && registry.get(id).is_none() {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: format!("Unknown TTS engine: {}", id),
+                ffmpeg_error: None,
+            }));
+        }
+    }
+
+    // The narration and its word timings outlive the request, to be rendered
+    // later through audio_filename
+    let workspace = match Workspace::create() {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            log::error!("Could not create a workspace: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: format!("TTS generation failed: {}", e),
+                ffmpeg_error: None,
+            }));
+        }
+    };
+    match generate_tts_audio(&registry, &workspace, text, lang, voice, speed, engine).await {
+        Ok(output) => {
+            workspace.keep(&output.filename);
+            workspace.keep(&word_timings_filename(&output.filename));
             Ok(HttpResponse::Ok().json(TtsResponse {
-                audio: format!("/download/{}", filename),
-                filename,
+                audio: format!("/download/{}", output.filename),
+                filename: output.filename,
+                engine: output.engine,
+                words: output.word_timings,
             }))
         }
         Err(e) => {
@@ -123,92 +387,652 @@ async fn generate_tts(req: web::Json<TtsRequest>) -> Result<HttpResponse> {
     }
 }
 
-async fn create_video(mut payload: Multipart) -> Result<HttpResponse> {
-    let mut bg_file_data = Vec::new();
-    let mut bg_filename = String::new();
-    let mut aspect_ratio = "16:9".to_string();
-    let mut audio_filename = String::new();
-    let mut original_text = String::new();
+async fn import_reddit(
+    client: web::Data<RedditClient>,
+    req: web::Json<RedditImportRequest>,
+) -> Result<HttpResponse> {
+    if let Err(e) = reddit::post_path(&req.url) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+            error: e.to_string(),
+            ffmpeg_error: None,
+        }));
+    }
 
-    // Parse multipart form data
+    let comment_limit = req.comments.unwrap_or(DEFAULT_COMMENT_LIMIT);
+    match client.fetch_post(&req.url, comment_limit).await {
+        Ok(post) => Ok(HttpResponse::Ok().json(post)),
+        Err(e) => {
+            log::error!("Reddit import failed: {}", e);
+            Ok(HttpResponse::BadGateway().json(ErrorResponse {
+                error: format!("Reddit import failed: {}", e),
+                ffmpeg_error: None,
+            }))
+        }
+    }
+}
+
+async fn music_tracks(library: web::Data<MusicLibrary>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(library.tracks()))
+}
+
+async fn upload_background(
+    library: web::Data<BackgroundLibrary>,
+    mut payload: Multipart,
+) -> Result<HttpResponse> {
+    let mut background = None;
+
+    // Stream the file straight to disk; backgrounds can be very large
     while let Some(mut field) = payload.try_next().await? {
-        match field.name() {
-            "bg_file" => {
-                bg_filename = field.content_disposition().get_filename().unwrap_or("unknown").to_string();
+        if field.name() == "file" && background.is_none() {
+            let name = field.content_disposition().get_filename().unwrap_or("unknown").to_string();
+            let upload = library.start_upload(&name);
+            let written: Result<()> = async {
+                let mut file = std::fs::File::create(&upload.path)?;
                 while let Some(chunk) = field.try_next().await? {
-                    bg_file_data.extend_from_slice(&chunk);
+                    file.write_all(&chunk)?;
                 }
+                Ok(())
             }
-            "aspect" => {
-                while let Some(chunk) = field.try_next().await? {
-                    aspect_ratio = String::from_utf8_lossy(&chunk).to_string();
-                }
-            }
-            "audio_filename" => {
-                while let Some(chunk) = field.try_next().await? {
-                    audio_filename = String::from_utf8_lossy(&chunk).to_string();
-                }
-            }
-            "text" => {
-                while let Some(chunk) = field.try_next().await? {
-                    original_text.push_str(&String::from_utf8_lossy(&chunk));
-                }
+            .await;
+
+            // Don't leave half a file behind when the client goes away
+            if let Err(e) = written {
+                library.abort_upload(upload);
+                return Err(e);
             }
-            _ => {}
+            background = Some(upload);
         }
     }
 
-    if bg_file_data.is_empty() {
+    let Some(upload) = background else {
         return Ok(HttpResponse::BadRequest().json(ErrorResponse {
             error: "No background file uploaded".to_string(),
             ffmpeg_error: None,
         }));
+    };
+
+    match library.finish_upload(upload).await {
+        Ok(background) => Ok(HttpResponse::Created().json(background)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(ErrorResponse {
+            error: format!("Not a usable image or video: {}", e),
+            ffmpeg_error: None,
+        })),
     }
+}
 
-    if audio_filename.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(ErrorResponse {
-            error: "No audio reference provided".to_string(),
+async fn list_backgrounds(library: web::Data<BackgroundLibrary>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(library.list()))
+}
+
+async fn get_background(
+    library: web::Data<BackgroundLibrary>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    match library.get(&path.into_inner()) {
+        Some(background) => Ok(HttpResponse::Ok().json(background)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Background not found".to_string(),
             ffmpeg_error: None,
-        }));
+        })),
+    }
+}
+
+async fn delete_background(
+    library: web::Data<BackgroundLibrary>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    match library.delete(&path.into_inner()) {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Background not found".to_string(),
+            ffmpeg_error: None,
+        })),
+        Err(e) => {
+            log::error!("Failed to delete background: {}", e);
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: format!("Failed to delete background: {}", e),
+                ffmpeg_error: None,
+            }))
+        }
+    }
+}
+
+// Streams an uploaded file into the workspace as `<prefix>_<name>` and returns
+// its path. Browsers send an empty part for a file input left blank, which
+// gives None.
+async fn save_upload(
+    field: &mut actix_multipart::Field,
+    workspace: &Workspace,
+    prefix: &str,
+) -> Result<Option<String>> {
+    let name = field.content_disposition().get_filename().unwrap_or("unknown").to_string();
+    let path = workspace.path(&format!("{}_{}", prefix, safe_filename(&name)));
+    let mut file = std::fs::File::create(&path)?;
+    let mut size = 0;
+    while let Some(chunk) = field.try_next().await? {
+        file.write_all(&chunk)?;
+        size += chunk.len();
+    }
+
+    if size == 0 {
+        std::fs::remove_file(&path)?;
+        return Ok(None);
+    }
+    Ok(Some(path))
+}
+
+async fn create_video(
+    config: web::Data<Config>,
+    queue: web::Data<JobQueue>,
+    backgrounds: web::Data<BackgroundLibrary>,
+    library: web::Data<MusicLibrary>,
+    mut payload: Multipart,
+) -> Result<HttpResponse> {
+    // The job renders into this workspace, so queued jobs hold paths rather
+    // than whole files. Dropping it on any error removes the uploads again.
+    let workspace = match Workspace::create() {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            log::error!("Could not create a workspace: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: format!("Failed to save the uploaded files: {}", e),
+                ffmpeg_error: None,
+            }));
+        }
+    };
+    let mut background = None;
+    let mut music = None;
+    let mut fields: HashMap<String, String> = HashMap::new();
+
+    // Parse multipart form data; the files are streamed to disk, the rest is text
+    while let Some(mut field) = payload.try_next().await? {
+        if field.name() == "bg_file" {
+            background = save_upload(&mut field, &workspace, "bg").await?.map(BackgroundSource::Upload);
+        } else if field.name() == "music_file" {
+            music = save_upload(&mut field, &workspace, "music").await?.map(MusicSource::Upload);
+        } else {
+            let name = field.name().to_string();
+            let mut value = Vec::new();
+            while let Some(chunk) = field.try_next().await? {
+                value.extend_from_slice(&chunk);
+            }
+            fields.insert(name, String::from_utf8_lossy(&value).to_string());
+        }
     }
 
-    match process_video(bg_file_data, bg_filename, aspect_ratio.clone(), audio_filename, original_text).await {
-        Ok((video_filename, caption_text, srt_filename)) => {
-            Ok(HttpResponse::Ok().json(VideoResponse {
-                video: format!("/download/{}", video_filename),
-                aspect: aspect_ratio,
-                captions: caption_text,
-                srt_file: srt_filename.map(|f| format!("/download/{}", f)),
+    let job = match render_request_from_fields(
+        &fields,
+        background,
+        music,
+        Some(workspace),
+        &backgrounds,
+        &library,
+        &config,
+    ) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: e.to_string(),
+                ffmpeg_error: None,
+            }));
+        }
+    };
+
+    match queue.enqueue(job) {
+        Ok(job_id) => Ok(HttpResponse::Accepted().json(JobCreatedResponse {
+            status_url: format!("/jobs/{}", job_id),
+            job_id,
+            status: "queued".to_string(),
+        })),
+        Err(e) => {
+            log::error!("Failed to enqueue video job: {}", e);
+            Ok(HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: format!("Failed to enqueue video job: {}", e),
+                ffmpeg_error: None,
             }))
         }
+    }
+}
+
+// Renders every entry of a JSON or CSV batch as its own job. Query parameters
+// act as defaults for all entries, e.g. /batch?aspect=9:16&background_id=...
+async fn create_batch(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    queue: web::Data<JobQueue>,
+    backgrounds: web::Data<BackgroundLibrary>,
+    library: web::Data<MusicLibrary>,
+) -> Result<HttpResponse> {
+    let is_csv = req.content_type().ends_with("csv");
+    let mut input = match parse_batch(&body, is_csv) {
+        Ok(input) => input,
         Err(e) => {
-            log::error!("Video creation failed: {}", e);
-            Ok(HttpResponse::InternalServerError().json(ErrorResponse {
-                error: format!("Video creation failed: {}", e),
-                ffmpeg_error: Some(e.to_string()),
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: e.to_string(),
+                ffmpeg_error: None,
+            }));
+        }
+    };
+    for (name, value) in query.into_inner() {
+        input.defaults.entry(name).or_insert(value);
+    }
+
+    // Nothing is queued unless every entry is valid
+    let mut jobs = Vec::with_capacity(input.entries.len());
+    for (i, entry) in input.entries.iter().enumerate() {
+        let fields = input.fields(entry);
+        match render_request_from_fields(&fields, None, None, None, &backgrounds, &library, &config) {
+            Ok(job) => jobs.push(job),
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                    error: format!("Entry {}: {}", i + 1, e),
+                    ffmpeg_error: None,
+                }));
+            }
+        }
+    }
+
+    let total = jobs.len();
+    match queue.enqueue_batch(jobs) {
+        Ok(batch_id) => Ok(HttpResponse::Accepted().json(BatchCreatedResponse {
+            status_url: format!("/batch/{}", batch_id),
+            batch_id,
+            status: "queued".to_string(),
+            total,
+        })),
+        Err(e) => {
+            log::error!("Failed to enqueue batch: {}", e);
+            Ok(HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: format!("Failed to enqueue batch: {}", e),
+                ffmpeg_error: None,
             }))
         }
     }
 }
 
+async fn batch_status(queue: web::Data<JobQueue>, path: web::Path<String>) -> Result<HttpResponse> {
+    match queue.batch_status(&path.into_inner()) {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Batch not found".to_string(),
+            ffmpeg_error: None,
+        })),
+    }
+}
+
+// ZIP of every finished video and its captions. Built once the whole batch
+// is done and kept next to the videos for later downloads.
+async fn batch_download(
+    req: HttpRequest,
+    queue: web::Data<JobQueue>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let id = path.into_inner();
+    let (Some(status), Some(files)) = (queue.batch_status(&id), queue.batch_files(&id)) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Batch not found".to_string(),
+            ffmpeg_error: None,
+        }));
+    };
+    if !status.is_finished() || files.is_empty() {
+        return Ok(HttpResponse::Conflict().json(ErrorResponse {
+            error: "The batch has no finished videos to download yet".to_string(),
+            ffmpeg_error: None,
+        }));
+    }
+
+    let zip_path = PathBuf::from(workspace::upload_path(&format!("batch_{}.zip", id)));
+    if !zip_path.exists() {
+        // Written under a temporary name so concurrent downloads never see
+        // half a ZIP
+        let partial = zip_path.with_extension("zip.part");
+        let target = zip_path.clone();
+        let written = web::block(move || {
+            write_zip(&partial, &files)?;
+            std::fs::rename(&partial, &target)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+
+        if let Err(e) = written {
+            log::error!("Failed to build ZIP for batch {}: {}", id, e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: format!("Failed to build ZIP: {}", e),
+                ffmpeg_error: None,
+            }));
+        }
+    }
+
+    let file = fs::NamedFile::open(&zip_path)?.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!("batch_{}.zip", id))],
+    });
+    Ok(file.into_response(&req))
+}
+
+// None when the request may use the admin routes
+fn check_admin(req: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    // Without a token the admin routes stay closed
+    let token = config.server.admin_token.as_str();
+    if token.is_empty() {
+        return Some(HttpResponse::Forbidden().json(ErrorResponse {
+            error: "Admin routes are disabled until an admin token is configured".to_string(),
+            ffmpeg_error: None,
+        }));
+    }
+
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if given == Some(token) {
+        None
+    } else {
+        Some(HttpResponse::Unauthorized().json(ErrorResponse {
+            error: "Missing or wrong admin token".to_string(),
+            ffmpeg_error: None,
+        }))
+    }
+}
+
+// What is in uploads/, newest first, with the job each workspace belongs to
+async fn storage_usage(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    queue: web::Data<JobQueue>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+
+    let mut items = web::block(storage::scan).await?;
+    let jobs = queue.jobs_by_workspace();
+    for item in &mut items {
+        item.job_id = jobs.get(&item.name).cloned();
+    }
+
+    Ok(HttpResponse::Ok().json(StorageResponse {
+        total_bytes: items.iter().map(|item| item.bytes).sum(),
+        policy: config.retention,
+        items,
+    }))
+}
+
+// Removes generated files by age or by job, outside the regular sweeps
+async fn purge_storage(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    queue: web::Data<JobQueue>,
+    body: web::Json<PurgeRequest>,
+) -> Result<HttpResponse> {
+    if let Some(response) = check_admin(&req, &config) {
+        return Ok(response);
+    }
+
+    let result = match (body.older_than_hours, body.job_id.as_deref()) {
+        (Some(hours), None) if hours >= 0.0 => {
+            web::block(move || storage::purge_older_than(hours)).await?
+        }
+        (None, Some(job_id)) => match queue.workspace(job_id) {
+            Some(workspace) => web::block(move || storage::purge_item(&workspace)).await?,
+            None => {
+                return Ok(HttpResponse::NotFound().json(ErrorResponse {
+                    error: "No finished job with that id".to_string(),
+                    ffmpeg_error: None,
+                }));
+            }
+        },
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                error: "Give either older_than_hours (0 or more) or job_id".to_string(),
+                ffmpeg_error: None,
+            }));
+        }
+    };
+
+    log::info!(
+        "Purged {} items from uploads, freeing {} bytes",
+        result.removed.len(),
+        result.freed_bytes
+    );
+    queue.evict_finished();
+    Ok(HttpResponse::Ok().json(result))
+}
+
+// Turns create-video form fields into a render request. Uploaded files win
+// over library picks and are rendered in the workspace they were saved into;
+// every other option falls back to its default when empty.
+fn render_request_from_fields(
+    fields: &HashMap<String, String>,
+    background: Option<BackgroundSource>,
+    music: Option<MusicSource>,
+    workspace: Option<Workspace>,
+    backgrounds: &BackgroundLibrary,
+    library: &MusicLibrary,
+    config: &Config,
+) -> anyhow::Result<RenderRequest> {
+    let field = |name: &str| fields.get(name).map(|v| v.trim()).unwrap_or_default();
+    let mut builder = RenderRequest::builder().output(config.video.output_settings());
+    if let Some(workspace) = workspace {
+        builder = builder.workspace(workspace);
+    }
+
+    let background = match background {
+        Some(background) => background,
+        None if !field("background_id").is_empty() => match backgrounds.get(field("background_id")) {
+            Some(background) => BackgroundSource::Library(backgrounds.path(&background)),
+            None => return Err(anyhow!("Unknown background: {}", field("background_id"))),
+        },
+        None => return Err(anyhow!("No background file uploaded")),
+    };
+    builder = builder.background(background);
+
+    // Narration: story segments, existing audio, or the script read by TTS
+    if !field("segments").is_empty() {
+        let segments: Vec<StorySegment> = serde_json::from_str(field("segments"))
+            .map_err(|e| anyhow!("Invalid segments: {}", e))?;
+        builder = builder.story(segments);
+    }
+    if !field("audio_filename").is_empty() {
+        builder = builder.audio(field("audio_filename"));
+    }
+    builder = builder
+        .text(fields.get("text").cloned().unwrap_or_default())
+        .lang(or_default(field("lang"), DEFAULT_LANG))
+        .voice(or_default(field("voice"), DEFAULT_VOICE))
+        .speed(parse_number("speed", field("speed"), 1.0)? as f32);
+    if !field("engine").is_empty() {
+        builder = builder.engine(field("engine"));
+    }
+
+    builder = builder
+        .aspect_ratio(or_default(field("aspect"), "16:9"))
+        .fit_mode(parse_option(field("fit_mode"))?)
+        .motion(parse_option(field("motion"))?)
+        .bg_start(parse_option(field("bg_start"))?)
+        .caption_align(parse_option(field("caption_align"))?)
+        .caption_style(parse_option(field("caption_style"))?)
+        .loudness(LoudnessTarget {
+            integrated: parse_number(
+                "loudness_target",
+                field("loudness_target"),
+                loudness::DEFAULT_TARGET_LUFS,
+            )?,
+            true_peak: parse_number("true_peak", field("true_peak"), loudness::DEFAULT_TRUE_PEAK)?,
+        });
+
+    // A title card is only drawn when the post title is known
+    if !field("post_title").is_empty() {
+        builder = builder.post(PostMeta {
+            title: field("post_title").to_string(),
+            subreddit: field("post_subreddit").trim_start_matches("r/").to_string(),
+            author: field("post_author").trim_start_matches("u/").to_string(),
+            score: field("post_score").parse().unwrap_or(0),
+        });
+    }
+
+    let music = match music {
+        Some(music) => Some(music),
+        None if !field("music").is_empty() => match library.path(field("music")) {
+            Some(path) => Some(MusicSource::Library(path)),
+            None => return Err(anyhow!("Unknown music track: {}", field("music"))),
+        },
+        None => None,
+    };
+    if let Some(source) = music {
+        builder = builder.music(MusicOptions {
+            source,
+            volume: parse_number("music_volume", field("music_volume"), music::DEFAULT_MUSIC_VOLUME)?,
+            fade: parse_number("music_fade", field("music_fade"), music::DEFAULT_MUSIC_FADE)?,
+        });
+    }
+
+    // Shorts platforms cap length, so long stories can be split into parts
+    if !field("max_part_seconds").is_empty() {
+        builder = builder.max_part_seconds(parse_number("max_part_seconds", field("max_part_seconds"), 0.0)?);
+    }
+
+    builder.build()
+}
+
+// Parses an optional form field, using the default when it was left empty
+fn parse_option<T>(value: &str) -> anyhow::Result<T>
+where
+    T: std::str::FromStr<Err = anyhow::Error> + Default,
+{
+    if value.is_empty() {
+        return Ok(T::default());
+    }
+
+    value.parse()
+}
+
+// Parses an optional numeric form field; the builder checks its range
+fn parse_number(name: &str, value: &str, default: f64) -> anyhow::Result<f64> {
+    if value.is_empty() {
+        return Ok(default);
+    }
+
+    value
+        .parse()
+        .map_err(|_| anyhow!("{} must be a number, got: {}", name, value))
+}
+
+fn or_default(value: &str, default: &str) -> String {
+    if value.is_empty() {
+        default.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+async fn job_events(queue: web::Data<JobQueue>, path: web::Path<String>) -> Result<HttpResponse> {
+    match queue.event_stream(&path.into_inner()) {
+        Some(stream) => Ok(HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(stream)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Job not found".to_string(),
+            ffmpeg_error: None,
+        })),
+    }
+}
+
+async fn job_status(queue: web::Data<JobQueue>, path: web::Path<String>) -> Result<HttpResponse> {
+    match queue.status(&path.into_inner()) {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse {
+            error: "Job not found".to_string(),
+            ffmpeg_error: None,
+        })),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
-    // Create uploads directory
-    std::fs::create_dir_all("uploads").unwrap();
+    // Bad settings stop the server before it binds, listing every problem
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    workspace::set_upload_dir(&config.server.upload_dir);
+    std::fs::create_dir_all(&config.server.upload_dir)?;
+
+    let registry = std::sync::Arc::new(TtsRegistry::new(
+        &config.tts.piper_model_dir,
+        &config.tts.piper_binary,
+        config.tts.base_wpm,
+    ));
+    let queue = web::Data::new(JobQueue::start(config.server.workers, registry.clone()));
+    let registry = web::Data::from(registry);
+
+    let reddit_client = web::Data::new(
+        RedditClient::new(&config.server.reddit_base_url)
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+
+    let music_library = web::Data::new(MusicLibrary::new(&config.server.music_dir));
+    let background_library = web::Data::new(
+        BackgroundLibrary::new(&config.server.background_dir)
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+
+    storage::start_sweeper(config.retention, queue.get_ref().clone());
+
+    let bind = config.server.bind.clone();
+    let upload_dir = config.server.upload_dir.clone();
+    let config = web::Data::new(config);
+    let templates = web::Data::new(templates());
 
-    log::info!("Starting Reddit Video Maker on 0.0.0.0:8080");
+    log::info!("Starting Reddit Video Maker on {}", bind);
 
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
+            .app_data(queue.clone())
+            .app_data(registry.clone())
+            .app_data(reddit_client.clone())
+            .app_data(music_library.clone())
+            .app_data(background_library.clone())
+            .app_data(config.clone())
+            .app_data(templates.clone())
             .route("/", web::get().to(index))
+            .route("/tts-engines", web::get().to(tts_engines))
+            .route("/voices", web::get().to(voices))
+            .route("/voices/{id}/sample", web::get().to(voice_sample))
             .route("/generate-tts", web::post().to(generate_tts))
+            .route("/reddit/import", web::post().to(import_reddit))
+            .route("/music", web::get().to(music_tracks))
+            .route("/backgrounds", web::post().to(upload_background))
+            .route("/backgrounds", web::get().to(list_backgrounds))
+            .route("/backgrounds/{id}", web::get().to(get_background))
+            .route("/backgrounds/{id}", web::delete().to(delete_background))
             .route("/create-video", web::post().to(create_video))
-            .service(fs::Files::new("/download", "uploads").show_files_listing())
+            .service(
+                web::resource("/batch")
+                    .app_data(web::PayloadConfig::new(BATCH_PAYLOAD_LIMIT))
+                    .route(web::post().to(create_batch)),
+            )
+            .route("/batch/{id}", web::get().to(batch_status))
+            .route("/batch/{id}/download", web::get().to(batch_download))
+            .route("/jobs/{id}", web::get().to(job_status))
+            .route("/jobs/{id}/events", web::get().to(job_events))
+            .route("/admin/storage", web::get().to(storage_usage))
+            .route("/admin/purge", web::post().to(purge_storage))
+            .service(fs::Files::new("/download", &upload_dir).show_files_listing())
     })
-    .bind("0.0.0.0:8080")?
+    .bind(bind)?
     .run()
     .await
 }
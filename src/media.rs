@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use tokio::process::Command;
+
+// Duration in seconds of any file ffprobe understands
+pub async fn probe_duration(path: &str) -> Result<f64> {
+    let duration_output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "json",
+            path,
+        ])
+        .output()
+        .await?;
+
+    if !duration_output.status.success() {
+        return Err(anyhow!("Could not determine duration of {}", path));
+    }
+
+    let duration_str = String::from_utf8_lossy(&duration_output.stdout);
+    let duration_json: serde_json::Value = serde_json::from_str(&duration_str)?;
+    duration_json["format"]["duration"]
+        .as_str()
+        .and_then(|s| s.parse::<f64>().ok())
+        .ok_or_else(|| anyhow!("Invalid duration format"))
+}
+
+// Joins audio files end to end into a single MP3. The concat filter resamples
+// as needed, so the parts may come from different engines or formats.
+pub async fn concat_audio(parts: &[String], output_path: &str) -> Result<()> {
+    let mut args: Vec<String> = vec!["-y".to_string()];
+    for part in parts {
+        args.extend(["-i".to_string(), part.clone()]);
+    }
+
+    let inputs: String = (0..parts.len()).map(|i| format!("[{}:a]", i)).collect();
+    args.extend([
+        "-filter_complex".to_string(),
+        format!("{}concat=n={}:v=0:a=1[a]", inputs, parts.len()),
+        "-map".to_string(),
+        "[a]".to_string(),
+        "-codec:a".to_string(),
+        "libmp3lame".to_string(),
+        "-b:a".to_string(),
+        "128k".to_string(),
+        output_path.to_string(),
+    ]);
+
+    let ffmpeg_output = Command::new("ffmpeg").args(&args).output().await?;
+
+    if !ffmpeg_output.status.success() {
+        return Err(anyhow!(
+            "Audio concatenation failed: {}",
+            String::from_utf8_lossy(&ffmpeg_output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "gif", "webp"];
+
+// Still images are looped into a video; everything else is treated as video
+pub fn is_image_file(filename: &str) -> bool {
+    let filename = filename.to_lowercase();
+    IMAGE_EXTENSIONS
+        .iter()
+        .any(|ext| filename.ends_with(&format!(".{}", ext)))
+}
+
+pub struct MediaInfo {
+    // Missing for still images
+    pub duration: Option<f64>,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Size of the first video stream, and the duration if the file has one
+pub async fn probe_media(path: &str) -> Result<MediaInfo> {
+    let probe_output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=width,height:format=duration",
+            "-of",
+            "json",
+            path,
+        ])
+        .output()
+        .await?;
+
+    if !probe_output.status.success() {
+        return Err(anyhow!(
+            "Could not read {}: {}",
+            path,
+            String::from_utf8_lossy(&probe_output.stderr).trim()
+        ));
+    }
+
+    let probe_json: serde_json::Value = serde_json::from_slice(&probe_output.stdout)?;
+    let stream = &probe_json["streams"][0];
+    let dimension = |key: &str| stream[key].as_u64().map(|value| value as u32);
+
+    match (dimension("width"), dimension("height")) {
+        (Some(width), Some(height)) => Ok(MediaInfo {
+            duration: probe_json["format"]["duration"]
+                .as_str()
+                .and_then(|s| s.parse::<f64>().ok()),
+            width,
+            height,
+        }),
+        _ => Err(anyhow!("{} has no video or image stream", path)),
+    }
+}
@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_MUSIC_VOLUME: f64 = 0.2;
+pub const DEFAULT_MUSIC_FADE: f64 = 2.0;
+// Volume is relative to the narration; both go down to 0
+pub const MAX_MUSIC_VOLUME: f64 = 1.0;
+pub const MAX_MUSIC_FADE: f64 = 30.0;
+
+const MUSIC_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "m4a", "aac", "flac"];
+
+#[derive(Serialize)]
+pub struct MusicTrack {
+    pub id: String,
+    pub name: String,
+}
+
+// Tracks dropped into a directory on the server, selectable by file name
+pub struct MusicLibrary {
+    dir: PathBuf,
+}
+
+impl MusicLibrary {
+    pub fn new(dir: &Path) -> Self {
+        MusicLibrary { dir: dir.to_path_buf() }
+    }
+
+    pub fn tracks(&self) -> Vec<MusicTrack> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let mut tracks: Vec<MusicTrack> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| MUSIC_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                    .unwrap_or(false)
+            })
+            .filter_map(|path| {
+                let id = path.file_name()?.to_str()?.to_string();
+                let name = path.file_stem()?.to_str()?.replace(['_', '-'], " ");
+                Some(MusicTrack { id, name })
+            })
+            .collect();
+
+        tracks.sort_by(|a, b| a.id.cmp(&b.id));
+        tracks
+    }
+
+    // Only ids that are listed resolve, so a request cannot point outside the
+    // library directory
+    pub fn path(&self, id: &str) -> Option<String> {
+        self.tracks()
+            .into_iter()
+            .find(|track| track.id == id)
+            .map(|track| self.dir.join(track.id).to_string_lossy().to_string())
+    }
+}
+
+pub enum MusicSource {
+    // Sent with the request and saved into its workspace; removed with it
+    Upload(String),
+    // Path of a track in the music library
+    Library(String),
+}
+
+pub struct MusicOptions {
+    pub source: MusicSource,
+    // Gain applied to the track before ducking, 0-1
+    pub volume: f64,
+    // Fade-in and fade-out length in seconds
+    pub fade: f64,
+}
+
+// Mixes the narration on `voice` with the looped track on `music` into `[a]`.
+// The narration drives a sidechain compressor on the music, so the music
+// drops under speech and comes back up in the pauses.
+pub fn music_filter(voice: &str, music: &str, volume: f64, fade: f64, duration: f64) -> String {
+    let fade = fade.clamp(0.0, duration / 2.0);
+    let fade_out_start = (duration - fade).max(0.0);
+
+    format!(
+        "{}asplit=2[voice][sidechain];\
+         {}volume={:.3},afade=t=in:st=0:d={:.3},afade=t=out:st={:.3}:d={:.3}[music];\
+         [music][sidechain]sidechaincompress=threshold=0.02:ratio=8:attack=20:release=400[ducked];\
+         [voice][ducked]amix=inputs=2:duration=first:dropout_transition=0:normalize=0[a]",
+        voice, music, volume, fade, fade_out_start, fade
+    )
+}
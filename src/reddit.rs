@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+// Reddit rejects requests without a descriptive user agent
+const USER_AGENT: &str = concat!("reddit-video-maker/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Serialize)]
+pub struct RedditComment {
+    pub author: String,
+    pub body: String,
+    pub score: i64,
+}
+
+#[derive(Serialize)]
+pub struct RedditPost {
+    pub title: String,
+    pub selftext: String,
+    pub author: String,
+    pub subreddit: String,
+    pub score: i64,
+    pub url: String,
+    pub comments: Vec<RedditComment>,
+    // Title and body joined, ready to send to /generate-tts
+    pub script: String,
+}
+
+pub struct RedditClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl RedditClient {
+    // `base_url` is normally https://www.reddit.com, but any server answering
+    // the same `<permalink>.json` paths works
+    pub fn new(base_url: &str) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(std::time::Duration::from_secs(15))
+            .build()?;
+
+        Ok(RedditClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http,
+        })
+    }
+
+    pub async fn fetch_post(&self, post_url: &str, comment_limit: usize) -> Result<RedditPost> {
+        let path = post_path(post_url)?;
+        let url = format!("{}{}.json", self.base_url, path);
+
+        let response = self
+            .http
+            .get(&url)
+            .query(&[("raw_json", "1"), ("sort", "top")])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Reddit returned {} for {}", response.status(), url));
+        }
+
+        let listing: Value = response.json().await?;
+        parse_thread(&listing, comment_limit)
+    }
+}
+
+// Turns any post URL (www/old/new reddit, redd.it short links, with or without
+// a trailing slug) into its `/comments/<id>` permalink path
+pub fn post_path(post_url: &str) -> Result<String> {
+    let url = reqwest::Url::parse(post_url.trim())
+        .map_err(|_| anyhow!("Not a valid URL: {}", post_url))?;
+
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    if url.host_str() == Some("redd.it") {
+        if let Some(id) = segments.first() {
+            return Ok(format!("/comments/{}", id));
+        }
+    }
+
+    let comments_index = segments
+        .iter()
+        .position(|s| *s == "comments")
+        .ok_or_else(|| anyhow!("Not a Reddit post URL: {}", post_url))?;
+    let id = segments
+        .get(comments_index + 1)
+        .ok_or_else(|| anyhow!("Reddit post URL has no post id: {}", post_url))?;
+
+    match segments.get(..comments_index) {
+        Some(["r", subreddit]) => Ok(format!("/r/{}/comments/{}", subreddit, id)),
+        _ => Ok(format!("/comments/{}", id)),
+    }
+}
+
+// A thread's JSON is a two element array: the post listing, then the comments
+fn parse_thread(listing: &Value, comment_limit: usize) -> Result<RedditPost> {
+    let post = &listing[0]["data"]["children"][0]["data"];
+    if !post.is_object() {
+        return Err(anyhow!("Reddit response did not contain a post"));
+    }
+
+    let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+
+    let mut comments: Vec<RedditComment> = listing[1]["data"]["children"]
+        .as_array()
+        .map(|children| {
+            children
+                .iter()
+                .filter(|child| child["kind"] == "t1")
+                .map(|child| &child["data"])
+                // Skip moderator notices and comments that no longer exist
+                .filter(|data| !data["stickied"].as_bool().unwrap_or(false))
+                .filter(|data| {
+                    !matches!(
+                        data["body"].as_str(),
+                        None | Some("[deleted]" | "[removed]")
+                    )
+                })
+                .map(|data| RedditComment {
+                    author: text(&data["author"]),
+                    body: text(&data["body"]),
+                    score: data["score"].as_i64().unwrap_or(0),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    comments.sort_by_key(|comment| std::cmp::Reverse(comment.score));
+    comments.truncate(comment_limit);
+
+    let title = text(&post["title"]);
+    let selftext = text(&post["selftext"]);
+    let script = if selftext.trim().is_empty() {
+        title.clone()
+    } else {
+        format!("{}\n\n{}", title, selftext.trim())
+    };
+
+    Ok(RedditPost {
+        author: text(&post["author"]),
+        subreddit: text(&post["subreddit"]),
+        score: post["score"].as_i64().unwrap_or(0),
+        url: format!("https://www.reddit.com{}", text(&post["permalink"])),
+        title,
+        selftext,
+        comments,
+        script,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_path_keeps_the_subreddit_and_drops_the_slug() {
+        for url in [
+            "https://www.reddit.com/r/AskReddit/comments/abc123/some_title/",
+            "https://old.reddit.com/r/AskReddit/comments/abc123/some_title",
+            "https://new.reddit.com/r/AskReddit/comments/abc123",
+            "  https://reddit.com/r/AskReddit/comments/abc123/?utm_source=share  ",
+        ] {
+            assert_eq!(post_path(url).unwrap(), "/r/AskReddit/comments/abc123", "{}", url);
+        }
+    }
+
+    #[test]
+    fn post_path_expands_short_links() {
+        assert_eq!(post_path("https://redd.it/abc123").unwrap(), "/comments/abc123");
+    }
+
+    #[test]
+    fn post_path_handles_links_without_a_subreddit() {
+        assert_eq!(
+            post_path("https://www.reddit.com/comments/abc123/title").unwrap(),
+            "/comments/abc123"
+        );
+    }
+
+    #[test]
+    fn post_path_rejects_other_urls() {
+        assert!(post_path("not a url").is_err());
+        assert!(post_path("https://www.reddit.com/r/AskReddit/").is_err());
+        assert!(post_path("https://www.reddit.com/r/AskReddit/comments/").is_err());
+        assert!(post_path("https://redd.it/").is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::backgrounds::BackgroundSource;
+use crate::captions::{CaptionAlignment, CaptionStyle};
+use crate::cards::PostMeta;
+use crate::loudness::{
+    LoudnessReport, LoudnessTarget, MAX_TARGET_LUFS, MAX_TRUE_PEAK, MIN_TARGET_LUFS, MIN_TRUE_PEAK,
+};
+use crate::music::{MusicOptions, MAX_MUSIC_FADE, MAX_MUSIC_VOLUME};
+use crate::story::{narrate_story, StoryRequest, StorySegment, TtsSettings};
+use crate::tts::{
+    generate_tts_audio, TtsRegistry, DEFAULT_LANG, DEFAULT_VOICE, MAX_SPEED, MIN_SPEED,
+};
+use crate::video::{
+    process_video, BackgroundStart, FitMode, Motion, OutputSettings, ProgressFn, RenderEvent,
+    RenderStage, VideoOptions, VideoPart, MAX_PART_SECONDS, MIN_PART_SECONDS,
+};
+use crate::workspace::Workspace;
+
+// Where the audio of a video comes from
+enum Narration {
+    // Read `text` aloud first
+    Tts { text: String, tts: TtsSettings },
+    // Audio already in uploads/, e.g. from /generate-tts; `text` is what it says
+    Audio { filename: String, text: String },
+    // Read a post and its comments, one card per segment
+    Story(StoryRequest),
+}
+
+// Everything needed to render one video, built with `RenderRequest::builder()`
+pub struct RenderRequest {
+    narration: Narration,
+    background: BackgroundSource,
+    options: VideoOptions,
+    workspace: Option<Workspace>,
+}
+
+impl RenderRequest {
+    pub fn builder() -> RenderRequestBuilder {
+        RenderRequestBuilder::default()
+    }
+
+    pub fn aspect_ratio(&self) -> &str {
+        &self.options.aspect_ratio
+    }
+}
+
+#[derive(Default)]
+pub struct RenderRequestBuilder {
+    text: String,
+    lang: Option<String>,
+    voice: Option<String>,
+    speed: Option<f32>,
+    engine: Option<String>,
+    audio_filename: Option<String>,
+    segments: Vec<StorySegment>,
+    background: Option<BackgroundSource>,
+    options: VideoOptions,
+    workspace: Option<Workspace>,
+}
+
+impl RenderRequestBuilder {
+    // The script: narrated by TTS, or describing the audio set with `audio()`
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = text.into();
+        self
+    }
+
+    pub fn lang(mut self, lang: impl Into<String>) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    pub fn voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = Some(voice.into());
+        self
+    }
+
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    // TTS engine id; "auto" or unset picks one by voice with fallbacks
+    pub fn engine(mut self, engine: impl Into<String>) -> Self {
+        self.engine = Some(engine.into());
+        self
+    }
+
+    // Narration already generated into uploads/, skipping TTS
+    pub fn audio(mut self, filename: impl Into<String>) -> Self {
+        self.audio_filename = Some(filename.into());
+        self
+    }
+
+    // Narrate these segments instead of `text`
+    pub fn story(mut self, segments: Vec<StorySegment>) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    pub fn background(mut self, background: BackgroundSource) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    // A file that is read in place and left alone
+    pub fn background_file(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_string_lossy().to_string();
+        self.background(BackgroundSource::Library(path))
+    }
+
+    pub fn aspect_ratio(mut self, aspect_ratio: impl Into<String>) -> Self {
+        self.options.aspect_ratio = aspect_ratio.into();
+        self
+    }
+
+    pub fn fit_mode(mut self, fit_mode: FitMode) -> Self {
+        self.options.fit_mode = fit_mode;
+        self
+    }
+
+    pub fn motion(mut self, motion: Motion) -> Self {
+        self.options.motion = motion;
+        self
+    }
+
+    pub fn bg_start(mut self, bg_start: BackgroundStart) -> Self {
+        self.options.bg_start = bg_start;
+        self
+    }
+
+    pub fn caption_align(mut self, caption_align: CaptionAlignment) -> Self {
+        self.options.caption_align = caption_align;
+        self
+    }
+
+    pub fn caption_style(mut self, caption_style: CaptionStyle) -> Self {
+        self.options.caption_style = caption_style;
+        self
+    }
+
+    // Shows a title card while the post title is read
+    pub fn post(mut self, post: PostMeta) -> Self {
+        self.options.post = Some(post);
+        self
+    }
+
+    pub fn music(mut self, music: MusicOptions) -> Self {
+        self.options.music = Some(music);
+        self
+    }
+
+    pub fn loudness(mut self, loudness: LoudnessTarget) -> Self {
+        self.options.loudness = loudness;
+        self
+    }
+
+    // Resolution, encoder preset and caption sizes
+    pub fn output(mut self, output: OutputSettings) -> Self {
+        self.options.output = output;
+        self
+    }
+
+    pub fn max_part_seconds(mut self, seconds: f64) -> Self {
+        self.options.max_part_seconds = Some(seconds);
+        self
+    }
+
+    // Renders into this workspace rather than a new one, e.g. the one the
+    // uploaded files were saved into
+    pub fn workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    // Checks every option, so a bad value fails here rather than in ffmpeg
+    pub fn build(self) -> Result<RenderRequest> {
+        let background = self
+            .background
+            .ok_or_else(|| anyhow!("No background given"))?;
+
+        if !matches!(self.options.aspect_ratio.as_str(), "16:9" | "9:16") {
+            return Err(anyhow!(
+                "Unsupported aspect ratio: {}",
+                self.options.aspect_ratio
+            ));
+        }
+
+        if let Some(speed) = self.speed {
+            check_range("speed", speed, MIN_SPEED, MAX_SPEED)?;
+        }
+        if let Some(seconds) = self.options.max_part_seconds {
+            check_range("max_part_seconds", seconds, MIN_PART_SECONDS, MAX_PART_SECONDS)?;
+        }
+        let loudness = &self.options.loudness;
+        check_range("loudness_target", loudness.integrated, MIN_TARGET_LUFS, MAX_TARGET_LUFS)?;
+        check_range("true_peak", loudness.true_peak, MIN_TRUE_PEAK, MAX_TRUE_PEAK)?;
+        if let Some(ref music) = self.options.music {
+            check_range("music_volume", music.volume, 0.0, MAX_MUSIC_VOLUME)?;
+            check_range("music_fade", music.fade, 0.0, MAX_MUSIC_FADE)?;
+        }
+
+        let tts = TtsSettings {
+            lang: self.lang.unwrap_or_else(|| DEFAULT_LANG.to_string()),
+            voice: self.voice.unwrap_or_else(|| DEFAULT_VOICE.to_string()),
+            speed: self.speed.unwrap_or(1.0),
+            engine: self.engine,
+        };
+
+        let narration = if !self.segments.is_empty() {
+            Narration::Story(StoryRequest {
+                segments: self.segments,
+                tts,
+            })
+        } else if let Some(filename) = self.audio_filename {
+            Narration::Audio {
+                filename,
+                text: self.text,
+            }
+        } else if !self.text.trim().is_empty() {
+            Narration::Tts {
+                text: self.text,
+                tts,
+            }
+        } else {
+            return Err(anyhow!("Nothing to narrate: give text, audio or story segments"));
+        };
+
+        Ok(RenderRequest {
+            narration,
+            background,
+            options: self.options,
+            workspace: self.workspace,
+        })
+    }
+}
+
+// Fails unless `value` lies within `min..=max`, which NaN never does
+pub fn check_range<T>(name: &str, value: T, min: T, max: T) -> Result<()>
+where
+    T: PartialOrd + std::fmt::Display,
+{
+    if value >= min && value <= max {
+        Ok(())
+    } else {
+        Err(anyhow!("{} must be between {} and {}, got {}", name, min, max, value))
+    }
+}
+
+pub struct RenderOutput {
+    // Directory under uploads/ holding the parts and their captions
+    pub workspace_id: String,
+    // In order; a single element unless the story was split into parts
+    pub parts: Vec<VideoPart>,
+    pub caption_text: Option<String>,
+    pub loudness: Option<LoudnessReport>,
+    // TTS engine of each narrated segment in order, or the one engine for
+    // plain text; empty when the narration was given
+    pub engines: Vec<String>,
+}
+
+// Narrates the request if needed and renders it into a workspace of its own,
+// or the one it was given. Only the videos and captions are left behind, and
+// nothing on failure.
+pub async fn render(
+    registry: &TtsRegistry,
+    request: RenderRequest,
+    progress: ProgressFn<'_>,
+) -> Result<RenderOutput> {
+    let workspace = match request.workspace {
+        Some(workspace) => workspace,
+        None => Workspace::create()?,
+    };
+    let mut options = request.options;
+
+    let (audio_filename, text, engines) = match request.narration {
+        Narration::Audio { filename, text } => (filename, text, Vec::new()),
+        Narration::Tts { text, tts } => {
+            progress(RenderEvent::Stage(RenderStage::Narrate));
+            let output = generate_tts_audio(
+                registry,
+                &workspace,
+                &text,
+                &tts.lang,
+                &tts.voice,
+                tts.speed,
+                tts.engine.as_deref(),
+            )
+            .await?;
+            (output.filename, text, vec![output.engine])
+        }
+        Narration::Story(story) => {
+            progress(RenderEvent::Stage(RenderStage::Narrate));
+            let narration = narrate_story(registry, &workspace, &story).await?;
+            options.cards = narration.cards;
+            (narration.audio_filename, narration.text, narration.engines)
+        }
+    };
+
+    let output = process_video(
+        &workspace,
+        request.background,
+        audio_filename,
+        text,
+        options,
+        progress,
+    )
+    .await?;
+
+    for part in &output.parts {
+        workspace.keep(&part.video_filename);
+        for file in &part.caption_files {
+            workspace.keep(&file.filename);
+        }
+    }
+
+    Ok(RenderOutput {
+        workspace_id: workspace.id().to_string(),
+        parts: output.parts,
+        caption_text: output.caption_text,
+        loudness: output.loudness,
+        engines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::MusicSource;
+
+    fn builder() -> RenderRequestBuilder {
+        RenderRequest::builder()
+            .text("A story.")
+            .background_file("background.png")
+    }
+
+    fn error(builder: RenderRequestBuilder) -> String {
+        builder.build().err().expect("build should fail").to_string()
+    }
+
+    #[test]
+    fn build_accepts_defaults() {
+        assert!(builder().build().is_ok());
+    }
+
+    #[test]
+    fn build_checks_ranges() {
+        assert!(error(builder().speed(0.0)).starts_with("speed"));
+        assert!(error(builder().speed(f32::NAN)).starts_with("speed"));
+        assert!(error(builder().max_part_seconds(0.0)).starts_with("max_part_seconds"));
+        assert!(error(builder().max_part_seconds(3601.0)).starts_with("max_part_seconds"));
+        assert!(error(builder().loudness(LoudnessTarget {
+            integrated: 0.0,
+            true_peak: -1.0,
+        }))
+        .starts_with("loudness_target"));
+        assert!(error(builder().loudness(LoudnessTarget {
+            integrated: -14.0,
+            true_peak: 3.0,
+        }))
+        .starts_with("true_peak"));
+        assert!(error(builder().music(MusicOptions {
+            source: MusicSource::Library("track.mp3".to_string()),
+            volume: 2.0,
+            fade: 1.0,
+        }))
+        .starts_with("music_volume"));
+    }
+
+    #[test]
+    fn build_accepts_values_at_the_limits() {
+        let request = builder()
+            .speed(MAX_SPEED)
+            .max_part_seconds(MIN_PART_SECONDS)
+            .loudness(LoudnessTarget {
+                integrated: MIN_TARGET_LUFS,
+                true_peak: MAX_TRUE_PEAK,
+            })
+            .build();
+        assert!(request.is_ok());
+    }
+}
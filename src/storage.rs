@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use reddit_video_maker::config::RetentionConfig;
+use reddit_video_maker::workspace::{self, upload_dir};
+
+use crate::jobs::JobQueue;
+
+// One entry directly under uploads/: a request's workspace, a batch ZIP or a
+// file from before workspaces existed
+#[derive(Serialize)]
+pub struct StorageItem {
+    pub name: String,
+    pub bytes: u64,
+    // Latest change to anything inside it
+    pub modified: DateTime<Utc>,
+    // Still being written by a render in progress
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct PurgeResult {
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+// Newest first
+pub fn scan() -> Vec<StorageItem> {
+    let Ok(entries) = std::fs::read_dir(upload_dir()) else {
+        return Vec::new();
+    };
+
+    let mut items: Vec<StorageItem> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == workspace::VOICE_SAMPLE_DIR {
+                return None;
+            }
+            let (bytes, modified) = usage(&entry.path())?;
+            Some(StorageItem {
+                active: workspace::is_active(&name),
+                name,
+                bytes,
+                modified: modified.into(),
+                job_id: None,
+            })
+        })
+        .collect();
+
+    items.sort_by_key(|item| std::cmp::Reverse(item.modified));
+    items
+}
+
+// Applies the policy once. Active workspaces are never touched.
+pub fn sweep(policy: &RetentionConfig) -> PurgeResult {
+    let items = scan();
+    let now = Utc::now();
+    let mut total: u64 = items.iter().map(|item| item.bytes).sum();
+    let mut result = PurgeResult::default();
+    // An age too large for i64 seconds just means nothing expires by age
+    let max_age_seconds =
+        i64::try_from(policy.max_age_hours.saturating_mul(3600)).unwrap_or(i64::MAX);
+
+    // Oldest first, skipping the newest `keep_last`
+    for item in items.iter().skip(policy.keep_last).rev() {
+        if item.active {
+            continue;
+        }
+
+        let expired = policy.max_age_hours > 0
+            && now.signed_duration_since(item.modified).num_seconds() > max_age_seconds;
+        let over_budget = policy.max_total_bytes > 0 && total > policy.max_total_bytes;
+        if !expired && !over_budget {
+            continue;
+        }
+
+        if remove(item, &mut result) {
+            total = total.saturating_sub(item.bytes);
+        }
+    }
+
+    result
+}
+
+// Removes everything last changed more than `hours` ago. A cutoff too far back
+// to represent matches nothing, so it purges nothing
+pub fn purge_older_than(hours: f64) -> PurgeResult {
+    let mut result = PurgeResult::default();
+    let Some(cutoff) = chrono::TimeDelta::try_seconds((hours * 3600.0) as i64)
+        .and_then(|age| Utc::now().checked_sub_signed(age))
+    else {
+        return result;
+    };
+
+    for item in scan() {
+        if !item.active && item.modified < cutoff {
+            remove(&item, &mut result);
+        }
+    }
+
+    result
+}
+
+// Removes one item by name, e.g. the workspace of a job
+pub fn purge_item(name: &str) -> PurgeResult {
+    let mut result = PurgeResult::default();
+    if let Some(item) = scan().into_iter().find(|item| item.name == name && !item.active) {
+        remove(&item, &mut result);
+    }
+    result
+}
+
+// Sweeps uploads/ every `sweep_minutes` for as long as the server runs, then
+// forgets the jobs whose output the sweep removed
+pub fn start_sweeper(policy: RetentionConfig, queue: JobQueue) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(policy.sweep_minutes * 60));
+        loop {
+            interval.tick().await;
+            match tokio::task::spawn_blocking(move || sweep(&policy)).await {
+                Ok(result) if !result.removed.is_empty() => log::info!(
+                    "Retention sweep removed {} items, freeing {} bytes",
+                    result.removed.len(),
+                    result.freed_bytes
+                ),
+                Ok(_) => {}
+                Err(e) => log::error!("Retention sweep failed: {}", e),
+            }
+
+            let evicted = queue.evict_finished();
+            if evicted > 0 {
+                log::info!("Forgot {} finished jobs", evicted);
+            }
+        }
+    });
+}
+
+fn remove(item: &StorageItem, result: &mut PurgeResult) -> bool {
+    let path = upload_dir().join(&item.name);
+    let removed = if path.is_dir() {
+        std::fs::remove_dir_all(&path)
+    } else {
+        std::fs::remove_file(&path)
+    };
+
+    match removed {
+        Ok(()) => {
+            result.removed.push(item.name.clone());
+            result.freed_bytes += item.bytes;
+            true
+        }
+        Err(e) => {
+            log::warn!("Could not remove {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+// Total size and latest modification time of a file or directory tree
+fn usage(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    let mut bytes = metadata.len();
+    let mut modified = metadata.modified().ok()?;
+
+    if metadata.is_dir() {
+        bytes = 0;
+        for entry in std::fs::read_dir(path).ok()?.filter_map(|entry| entry.ok()) {
+            if let Some((size, changed)) = usage(&entry.path()) {
+                bytes += size;
+                modified = modified.max(changed);
+            }
+        }
+    }
+
+    Some((bytes, modified))
+}
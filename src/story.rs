@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::captions::{distribute_words, remove_word_timings, save_word_timings};
+use crate::cards::{comment_card, post_card, Card, PostMeta};
+use crate::media::{concat_audio, probe_duration};
+use crate::tts::{generate_tts_audio, TtsRegistry};
+use crate::workspace::{upload_path, Workspace};
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Post,
+    Comment,
+}
+
+// One narrated part of a video: the post itself or one of its comments
+#[derive(Deserialize, Clone)]
+pub struct StorySegment {
+    pub kind: SegmentKind,
+    // What is read aloud
+    pub text: String,
+    // Shown on the post's card instead of the full text
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub subreddit: String,
+    #[serde(default)]
+    pub score: i64,
+}
+
+impl StorySegment {
+    fn card(&self, start: f64, end: f64) -> Card {
+        match self.kind {
+            SegmentKind::Post => {
+                let post = PostMeta {
+                    title: if self.title.trim().is_empty() {
+                        self.text.clone()
+                    } else {
+                        self.title.clone()
+                    },
+                    subreddit: self.subreddit.clone(),
+                    author: self.author.clone(),
+                    score: self.score,
+                };
+                post_card(&post, start, end)
+            }
+            SegmentKind::Comment => comment_card(&self.author, &self.text, self.score, start, end),
+        }
+    }
+}
+
+pub struct TtsSettings {
+    pub lang: String,
+    pub voice: String,
+    pub speed: f32,
+    pub engine: Option<String>,
+}
+
+pub struct StoryRequest {
+    pub segments: Vec<StorySegment>,
+    pub tts: TtsSettings,
+}
+
+pub struct Narration {
+    pub audio_filename: String,
+    pub text: String,
+    // One card per segment, spanning that segment's audio
+    pub cards: Vec<Card>,
+    // TTS engine that read each segment, so a fallback to silence shows up
+    pub engines: Vec<String>,
+}
+
+// Synthesizes every segment in order and joins them into one audio file with
+// word timings covering the whole story, as `story.mp3` in the workspace
+pub async fn narrate_story(
+    registry: &TtsRegistry,
+    workspace: &Workspace,
+    story: &StoryRequest,
+) -> Result<Narration> {
+    let segments: Vec<&StorySegment> = story
+        .segments
+        .iter()
+        .filter(|segment| !segment.text.trim().is_empty())
+        .collect();
+    if segments.is_empty() {
+        return Err(anyhow!("No segment has any text to read"));
+    }
+
+    let mut parts = Vec::new();
+
+    let result = async {
+        let mut words = Vec::new();
+        let mut cards = Vec::new();
+        let mut engines = Vec::new();
+        let mut offset = 0.0;
+
+        for (i, segment) in segments.iter().enumerate() {
+            let output = generate_tts_audio(
+                registry,
+                workspace,
+                &segment.text,
+                &story.tts.lang,
+                &story.tts.voice,
+                story.tts.speed,
+                story.tts.engine.as_deref(),
+            )
+            .await?;
+
+            // Every segment is narrated to the same file, so move each one
+            // aside before the next segment reuses the name
+            let part_path = workspace.path(&format!("segment{}.mp3", i));
+            std::fs::rename(upload_path(&output.filename), &part_path)?;
+            remove_word_timings(&output.filename);
+            parts.push(part_path.clone());
+            engines.push(output.engine);
+
+            let duration = probe_duration(&part_path).await?;
+            match output.word_timings {
+                Some(timings) => words.extend(timings.into_iter().map(|mut word| {
+                    word.start += offset;
+                    word.end = (word.end + offset).min(offset + duration);
+                    word
+                })),
+                None => words.extend(distribute_words(&segment.text, offset, offset + duration)),
+            }
+
+            cards.push(segment.card(offset, offset + duration));
+            offset += duration;
+        }
+
+        let audio_filename = workspace.file("story.mp3");
+        concat_audio(&parts, &upload_path(&audio_filename)).await?;
+        save_word_timings(&audio_filename, &words)?;
+
+        Ok(Narration {
+            audio_filename,
+            text: segments
+                .iter()
+                .map(|segment| segment.text.trim())
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            cards,
+            engines,
+        })
+    }
+    .await;
+
+    for part in &parts {
+        let _ = std::fs::remove_file(part);
+    }
+
+    result
+}
@@ -1,153 +1,563 @@
-
+use crate::captions::{distribute_words, save_word_timings, split_sentences, WordTiming};
+use crate::media::{concat_audio, probe_duration};
+use crate::render::check_range;
+use crate::workspace::Workspace;
 use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+
+// Where Piper `.onnx` voice models are looked for
+pub const DEFAULT_PIPER_MODEL_DIR: &str = "models/piper";
+pub const DEFAULT_PIPER_BINARY: &str = "piper";
+// Speaking rate at speed 1.0, in words per minute
+pub const DEFAULT_BASE_WPM: u32 = 175;
+// Used when a request does not pick a language or voice
+pub const DEFAULT_LANG: &str = "en";
+pub const DEFAULT_VOICE: &str = "us";
+// Speed multipliers engines are asked for; 0 would never finish speaking
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+
+// Voice id shown in the UI, display name, language, locale, espeak-ng voice
+const ESPEAK_VOICES: &[(&str, &str, &str, &str, &str)] = &[
+    ("com.au", "Australian", "en", "en-AU", "en-au"),
+    ("co.uk", "British", "en", "en-GB", "en-gb"),
+    ("us", "American", "en", "en-US", "en-us"),
+    ("ca", "Canadian", "en", "en-CA", "en-ca"),
+    ("ind", "Indian", "en", "en-IN", "en-in"),
+    ("za", "South African", "en", "en-ZA", "en-za"),
+    ("ie", "Irish", "en", "en-IE", "en-ie"),
+    ("nz", "New Zealand", "en", "en-NZ", "en-nz"),
+    ("ng", "Nigerian", "en", "en-NG", "en-ng"),
+    ("tt", "Trinidad & Tobago", "en", "en-TT", "en-tt"),
+    ("es", "Spanish (Spain)", "es", "es-ES", "es"),
+    ("mx", "Mexican Spanish", "es", "es-MX", "es-mx"),
+    ("ar", "Argentinian Spanish", "es", "es-AR", "es-ar"),
+    ("cl", "Chilean Spanish", "es", "es-CL", "es-cl"),
+];
+
+#[derive(Clone, Serialize)]
+pub struct VoiceInfo {
+    pub id: String,
+    pub name: String,
+    pub lang: String,
+    // BCP 47 tag, e.g. en-GB
+    pub locale: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+}
 
+#[derive(Clone, Copy, Serialize)]
+pub struct EngineCapabilities {
+    pub voice_selection: bool,
+    pub speed_control: bool,
+    // False for engines that only produce placeholder audio
+    pub speech: bool,
+}
+
+pub trait TtsEngine: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn capabilities(&self) -> EngineCapabilities;
+    fn voices(&self) -> Vec<VoiceInfo>;
+    // Writes the synthesized audio to `audio_path`
+    fn synthesize(&self, text: &str, voice: &str, speed: f32, audio_path: &str) -> Result<()>;
+}
+
+pub struct TtsOutput {
+    pub filename: String,
+    pub engine: String,
+    // Only engines that produce speech report word timings
+    pub word_timings: Option<Vec<WordTiming>>,
+}
+
+pub struct TtsRegistry {
+    // Also the fallback order when no engine is requested
+    engines: Vec<Arc<dyn TtsEngine>>,
+}
+
+impl TtsRegistry {
+    pub fn new(piper_model_dir: &Path, piper_binary: &str, base_wpm: u32) -> Self {
+        TtsRegistry {
+            engines: vec![
+                Arc::new(EspeakEngine { base_wpm }),
+                Arc::new(FestivalEngine),
+                Arc::new(PiperEngine::discover(piper_model_dir, piper_binary)),
+                Arc::new(SilenceEngine { base_wpm }),
+            ],
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&dyn TtsEngine> {
+        self.engines
+            .iter()
+            .find(|engine| engine.id() == id)
+            .map(|engine| engine.as_ref())
+    }
+
+    pub fn engines(&self) -> impl Iterator<Item = &dyn TtsEngine> {
+        self.engines.iter().map(|engine| engine.as_ref())
+    }
+
+    fn engine_for_voice(&self, voice: &str) -> Option<&dyn TtsEngine> {
+        self.engines()
+            .find(|engine| engine.voices().iter().any(|v| v.id == voice))
+    }
+
+    fn shared(&self, id: &str) -> Option<Arc<dyn TtsEngine>> {
+        self.engines.iter().find(|engine| engine.id() == id).cloned()
+    }
+}
+
+// Writes `narration.mp3` into the workspace, replacing any earlier narration
 pub async fn generate_tts_audio(
+    registry: &TtsRegistry,
+    workspace: &Workspace,
     text: &str,
     _lang: &str,
     voice: &str,
     speed: f32,
-) -> Result<(String, String)> {
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-
-    let filename = format!("output_{}.mp3", timestamp);
-    let audio_path = format!("uploads/{}", filename);
-
-    // Use espeak-ng for TTS generation (pure system command approach)
-    let voice_name = match voice {
-        "com.au" => "en-au",
-        "co.uk" => "en-gb", 
-        "us" => "en-us",
-        "ca" => "en-ca",
-        "ind" => "en-in",
-        "za" => "en-za",
-        "ie" => "en-ie",
-        "nz" => "en-nz",
-        "ng" => "en-ng",
-        "tt" => "en-tt",
-        "es" => "es",
-        "mx" => "es-mx",
-        "ar" => "es-ar",
-        "cl" => "es-cl",
-        _ => "en-us"
+    engine: Option<&str>,
+) -> Result<TtsOutput> {
+    check_range("speed", speed, MIN_SPEED, MAX_SPEED)?;
+
+    let filename = workspace.file("narration.mp3");
+    let audio_path = workspace.path("narration.mp3");
+
+    // An explicitly requested engine never falls back to another one
+    let candidates: Vec<Arc<dyn TtsEngine>> = match engine {
+        Some(id) if id != "auto" => {
+            vec![registry.shared(id).ok_or_else(|| anyhow!("Unknown TTS engine: {}", id))?]
+        }
+        _ => {
+            // Try the engine that owns the requested voice before the fallbacks;
+            // the sort is stable so the rest keep their order
+            let owner = registry.engine_for_voice(voice).map(|o| o.id());
+            let mut engines = registry.engines.clone();
+            engines.sort_by_key(|e| Some(e.id()) != owner);
+            engines
+        }
     };
 
-    // Calculate words per minute based on speed (normal is around 175 wpm)
-    let base_wpm = 175;
-    let wpm = (base_wpm as f32 * speed) as u32;
+    let mut last_error = anyhow!("No TTS engine available");
+    for candidate in candidates {
+        let result = if candidate.capabilities().speech {
+            synthesize_with_timings(&candidate, text, voice, speed, &audio_path)
+                .await
+                .map(Some)
+        } else {
+            synthesize(&candidate, text, voice, speed, &audio_path)
+                .await
+                .map(|_| None)
+        };
 
-    // Generate audio using espeak-ng
-    let espeak_output = Command::new("espeak-ng")
-        .args([
-            "-v", voice_name,
-            "-s", &wpm.to_string(),
-            "-w", &audio_path,
-            text,
-        ])
-        .output();
+        match result {
+            Ok(word_timings) => {
+                // Verify the audio file was created
+                if !std::path::Path::new(&audio_path).exists() {
+                    return Err(anyhow!("Audio file was not generated"));
+                }
+
+                if !candidate.capabilities().speech {
+                    log::warn!(
+                        "Generated fallback audio with the {} engine. Install espeak-ng or festival for actual TTS.",
+                        candidate.id()
+                    );
+                }
+
+                if let Some(ref words) = word_timings {
+                    if let Err(e) = save_word_timings(&filename, words) {
+                        log::warn!("Could not save word timings for {}: {}", filename, e);
+                    }
+                }
 
-    match espeak_output {
-        Ok(output) => {
-            if !output.status.success() {
-                let error = String::from_utf8_lossy(&output.stderr);
-                return Err(anyhow!("espeak-ng TTS failed: {}", error));
+                return Ok(TtsOutput {
+                    filename,
+                    engine: candidate.id().to_string(),
+                    word_timings,
+                });
+            }
+            Err(e) => {
+                log::warn!("TTS engine {} failed: {}", candidate.id(), e);
+                last_error = e;
             }
         }
-        Err(_) => {
-            // Fallback to festival if espeak-ng is not available
-            let festival_output = Command::new("festival")
-                .args([
-                    "--tts",
-                ])
-                .stdin(std::process::Stdio::piped())
-                .stdout(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::piped())
-                .spawn();
-
-            match festival_output {
-                Ok(mut process) => {
-                    use std::io::Write;
-                    if let Some(stdin) = process.stdin.as_mut() {
-                        let _ = stdin.write_all(text.as_bytes());
-                    }
-                    
-                    let output = process.wait_with_output()?;
-                    if !output.status.success() {
-                        return Err(anyhow!("Festival TTS also failed. No TTS engine available."));
-                    }
-                    
-                    // Festival outputs to stdout, we need to convert to mp3
-                    let temp_wav = format!("uploads/temp_{}.wav", timestamp);
-                    std::fs::write(&temp_wav, &output.stdout)?;
-                    
-                    // Convert WAV to MP3 using FFmpeg
-                    let ffmpeg_output = Command::new("ffmpeg")
-                        .args([
-                            "-y",
-                            "-i", &temp_wav,
-                            "-codec:a", "libmp3lame",
-                            "-b:a", "128k",
-                            &audio_path,
-                        ])
-                        .output()?;
-
-                    // Clean up temp file
-                    let _ = std::fs::remove_file(&temp_wav);
-
-                    if !ffmpeg_output.status.success() {
-                        let error = String::from_utf8_lossy(&ffmpeg_output.stderr);
-                        return Err(anyhow!("FFmpeg conversion failed: {}", error));
-                    }
+    }
+
+    Err(last_error)
+}
+
+// Engines run their binary and wait for it, so synthesis goes to the blocking
+// pool rather than stalling every other task on the runtime
+async fn synthesize(
+    engine: &Arc<dyn TtsEngine>,
+    text: &str,
+    voice: &str,
+    speed: f32,
+    audio_path: &str,
+) -> Result<()> {
+    let engine = engine.clone();
+    let (text, voice, audio_path) = (text.to_string(), voice.to_string(), audio_path.to_string());
+    tokio::task::spawn_blocking(move || engine.synthesize(&text, &voice, speed, &audio_path))
+        .await?
+}
+
+// Synthesizes one sentence at a time so each sentence's measured duration
+// anchors its words, then joins the parts into `audio_path`.
+async fn synthesize_with_timings(
+    engine: &Arc<dyn TtsEngine>,
+    text: &str,
+    voice: &str,
+    speed: f32,
+    audio_path: &str,
+) -> Result<Vec<WordTiming>> {
+    let sentences = split_sentences(text);
+
+    if sentences.len() <= 1 {
+        synthesize(engine, text, voice, speed, audio_path).await?;
+        let duration = probe_duration(audio_path).await?;
+        return Ok(distribute_words(text, 0.0, duration));
+    }
+
+    let stem = audio_path.trim_end_matches(".mp3");
+    let mut parts = Vec::new();
+
+    let result = async {
+        let mut timings = Vec::new();
+        let mut offset = 0.0;
+
+        for (i, sentence) in sentences.iter().enumerate() {
+            let part_path = format!("{}_part{}.mp3", stem, i);
+            parts.push(part_path.clone());
+
+            synthesize(engine, sentence, voice, speed, &part_path).await?;
+            let duration = probe_duration(&part_path).await?;
+            timings.extend(distribute_words(sentence, offset, offset + duration));
+            offset += duration;
+        }
+
+        concat_audio(&parts, audio_path).await?;
+        Ok(timings)
+    }
+    .await;
+
+    for part in &parts {
+        let _ = std::fs::remove_file(part);
+    }
+
+    result
+}
+
+pub struct EspeakEngine {
+    base_wpm: u32,
+}
+
+impl TtsEngine for EspeakEngine {
+    fn id(&self) -> &'static str {
+        "espeak-ng"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            voice_selection: true,
+            speed_control: true,
+            speech: true,
+        }
+    }
+
+    fn voices(&self) -> Vec<VoiceInfo> {
+        ESPEAK_VOICES
+            .iter()
+            .map(|(id, name, lang, locale, _)| VoiceInfo {
+                id: id.to_string(),
+                name: name.to_string(),
+                lang: lang.to_string(),
+                locale: locale.to_string(),
+                // Every accent uses espeak-ng's default male variant
+                gender: Some("male".to_string()),
+            })
+            .collect()
+    }
+
+    fn synthesize(&self, text: &str, voice: &str, speed: f32, audio_path: &str) -> Result<()> {
+        let voice_name = ESPEAK_VOICES
+            .iter()
+            .find(|(id, ..)| *id == voice)
+            .map(|(.., espeak_voice)| *espeak_voice)
+            .unwrap_or("en-us");
+
+        // Calculate words per minute based on speed
+        let wpm = (self.base_wpm as f32 * speed) as u32;
+
+        let output = Command::new("espeak-ng")
+            .args(["-v", voice_name, "-s", &wpm.to_string(), "-w", audio_path, text])
+            .output()?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("espeak-ng TTS failed: {}", error));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct FestivalEngine;
+
+impl TtsEngine for FestivalEngine {
+    fn id(&self) -> &'static str {
+        "festival"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            voice_selection: false,
+            speed_control: false,
+            speech: true,
+        }
+    }
+
+    fn voices(&self) -> Vec<VoiceInfo> {
+        vec![VoiceInfo {
+            id: "default".to_string(),
+            name: "Festival default".to_string(),
+            lang: "en".to_string(),
+            // kal_diphone, the voice festival ships with
+            locale: "en-US".to_string(),
+            gender: Some("male".to_string()),
+        }]
+    }
+
+    fn synthesize(&self, text: &str, _voice: &str, _speed: f32, audio_path: &str) -> Result<()> {
+        use std::io::Write;
+
+        let mut process = Command::new("festival")
+            .args(["--tts"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        if let Some(stdin) = process.stdin.as_mut() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+
+        let output = process.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!("Festival TTS failed"));
+        }
+
+        // Festival outputs to stdout, we need to convert to mp3
+        let temp_wav = format!("{}.wav", audio_path.trim_end_matches(".mp3"));
+        std::fs::write(&temp_wav, &output.stdout)?;
+
+        convert_wav_to_mp3(&temp_wav, audio_path)
+    }
+}
+
+struct PiperModel {
+    id: String,
+    name: String,
+    lang: String,
+    locale: String,
+    path: PathBuf,
+}
+
+// Neural TTS through a local `piper` binary and its `.onnx` voice models
+pub struct PiperEngine {
+    binary: String,
+    models: Vec<PiperModel>,
+}
+
+impl PiperEngine {
+    // Scans `model_dir` for voice models named like `en_US-lessac-medium.onnx`
+    pub fn discover(model_dir: &Path, binary: &str) -> Self {
+        let mut models = Vec::new();
+
+        if let Ok(entries) = std::fs::read_dir(model_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("onnx") {
+                    continue;
                 }
-                Err(_) => {
-                    // Final fallback: generate silence with text overlay (for demo purposes)
-                    return generate_fallback_audio(text, &audio_path, speed).await;
+                let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+
+                let mut parts = stem.split('-');
+                let locale = parts.next().unwrap_or(stem);
+                let speaker = parts.next().unwrap_or(stem);
+                let quality = parts.next();
+
+                let mut name = format!("{} ({})", speaker.replace('_', " "), locale);
+                if let Some(quality) = quality {
+                    name = format!("{} {}", name, quality);
                 }
+
+                models.push(PiperModel {
+                    id: format!("piper:{}", stem),
+                    name,
+                    lang: locale.split('_').next().unwrap_or(locale).to_lowercase(),
+                    locale: locale.replace('_', "-"),
+                    path,
+                });
             }
         }
+
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        log::info!(
+            "Found {} Piper voice models in {}",
+            models.len(),
+            model_dir.display()
+        );
+
+        PiperEngine {
+            binary: binary.to_string(),
+            models,
+        }
+    }
+}
+
+impl TtsEngine for PiperEngine {
+    fn id(&self) -> &'static str {
+        "piper"
     }
 
-    // Verify the audio file was created
-    if !std::path::Path::new(&audio_path).exists() {
-        return Err(anyhow!("Audio file was not generated"));
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            voice_selection: true,
+            speed_control: true,
+            speech: true,
+        }
     }
 
-    Ok((audio_path, filename))
+    fn voices(&self) -> Vec<VoiceInfo> {
+        self.models
+            .iter()
+            .map(|model| VoiceInfo {
+                id: model.id.clone(),
+                name: model.name.clone(),
+                lang: model.lang.clone(),
+                locale: model.locale.clone(),
+                // Piper models do not record the speaker's gender
+                gender: None,
+            })
+            .collect()
+    }
+
+    fn synthesize(&self, text: &str, voice: &str, speed: f32, audio_path: &str) -> Result<()> {
+        use std::io::Write;
+
+        let model = self
+            .models
+            .iter()
+            .find(|model| model.id == voice)
+            .ok_or_else(|| anyhow!("Unknown Piper voice: {}", voice))?;
+
+        // Piper stretches phoneme length, so faster speech is a shorter scale
+        let length_scale = 1.0 / speed.max(0.1);
+        let temp_wav = format!("{}.wav", audio_path.trim_end_matches(".mp3"));
+
+        let mut process = Command::new(&self.binary)
+            .args(["--model"])
+            .arg(&model.path)
+            .args([
+                "--output_file",
+                &temp_wav,
+                "--length_scale",
+                &length_scale.to_string(),
+            ])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        if let Some(mut stdin) = process.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+
+        let output = process.wait_with_output()?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&temp_wav);
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Piper TTS failed: {}", error));
+        }
+
+        convert_wav_to_mp3(&temp_wav, audio_path)
+    }
 }
 
-async fn generate_fallback_audio(text: &str, audio_path: &str, speed: f32) -> Result<(String, String)> {
-    // Calculate duration based on text length and speed
-    let words = text.split_whitespace().count();
-    let base_wpm = 175.0; // words per minute
-    let duration = (words as f32) / (base_wpm * speed) * 60.0;
-    let duration = duration.max(2.0); // minimum 2 seconds
+// Last resort: silence long enough to read the text, so a video can still be made
+pub struct SilenceEngine {
+    base_wpm: u32,
+}
 
-    // Generate silence with the calculated duration
+impl TtsEngine for SilenceEngine {
+    fn id(&self) -> &'static str {
+        "silence"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            voice_selection: false,
+            speed_control: true,
+            speech: false,
+        }
+    }
+
+    fn voices(&self) -> Vec<VoiceInfo> {
+        Vec::new()
+    }
+
+    fn synthesize(&self, text: &str, _voice: &str, speed: f32, audio_path: &str) -> Result<()> {
+        // Calculate duration based on text length and speed
+        let words = text.split_whitespace().count();
+        let duration = (words as f32) / (self.base_wpm as f32 * speed) * 60.0;
+        let duration = duration.max(2.0); // minimum 2 seconds
+
+        // Generate silence with the calculated duration
+        let ffmpeg_output = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-f", "lavfi",
+                "-i", "anullsrc=channel_layout=stereo:sample_rate=44100",
+                "-t", &duration.to_string(),
+                "-codec:a", "libmp3lame",
+                "-b:a", "128k",
+                audio_path,
+            ])
+            .output()?;
+
+        if !ffmpeg_output.status.success() {
+            let error = String::from_utf8_lossy(&ffmpeg_output.stderr);
+            return Err(anyhow!("Fallback audio generation failed: {}", error));
+        }
+
+        Ok(())
+    }
+}
+
+// Converts a WAV to MP3 using FFmpeg and removes the WAV
+fn convert_wav_to_mp3(wav_path: &str, audio_path: &str) -> Result<()> {
     let ffmpeg_output = Command::new("ffmpeg")
         .args([
             "-y",
-            "-f", "lavfi",
-            "-i", &format!("anullsrc=channel_layout=stereo:sample_rate=44100"),
-            "-t", &duration.to_string(),
+            "-i", wav_path,
             "-codec:a", "libmp3lame",
             "-b:a", "128k",
             audio_path,
         ])
-        .output()?;
+        .output();
+
+    // Clean up temp file
+    let _ = std::fs::remove_file(wav_path);
 
+    let ffmpeg_output = ffmpeg_output?;
     if !ffmpeg_output.status.success() {
         let error = String::from_utf8_lossy(&ffmpeg_output.stderr);
-        return Err(anyhow!("Fallback audio generation failed: {}", error));
-    }
-
-    log::warn!("Generated fallback silent audio. Install espeak-ng or festival for actual TTS.");
-    
-    let filename = std::path::Path::new(audio_path)
-        .file_name()
-        .unwrap()
-        .to_string_lossy()
-        .to_string();
-    
-    Ok((audio_path.to_string(), filename))
+        return Err(anyhow!("FFmpeg conversion failed: {}", error));
+    }
+
+    Ok(())
 }
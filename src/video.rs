@@ -1,50 +1,295 @@
-use crate::captions::{generate_captions_from_text, save_srt_file};
+use crate::captions::{
+    build_cues, load_word_timings, resolve_word_timings, save_caption_files, CaptionAlignment, CaptionFile,
+    CaptionFormat, CaptionStyle, WordTiming, DEFAULT_CAPTION_FONT_SIZE,
+    DEFAULT_CAPTION_FONT_SIZE_VERTICAL,
+};
+use crate::cards::{card_filters, title_card, Card, PostMeta};
+use crate::loudness::{
+    loudness_report, measure_loudness, normalize_filter, LoudnessReport, LoudnessTarget,
+};
+use crate::backgrounds::BackgroundSource;
+use crate::media::{is_image_file, probe_duration};
+use crate::music::{music_filter, MusicOptions, MusicSource};
+use crate::workspace::{upload_path, Workspace};
 use anyhow::{anyhow, Result};
-use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use rand::Rng;
+use serde::Serialize;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
 
+#[derive(Clone, Copy, Serialize, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderStage {
+    Narrate,
+    AudioConvert,
+    Probe,
+    Captions,
+    Encode,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RenderEvent {
+    Stage(RenderStage),
+    // Percentage of the encode completed, 0-100
+    Progress(f64),
+}
+
+// How far the Ken Burns presets zoom in over the whole video
+const MOTION_ZOOM: f64 = 0.2;
+const MOTION_OVERSAMPLE: u32 = 2;
+const MOTION_FPS: u32 = 30;
+
+// A part is cut at a sentence end no earlier than this share of the limit
+const MIN_PART_FRACTION: f64 = 0.5;
+// Shorter parts would mostly be cut mid-sentence
+pub const MIN_PART_SECONDS: f64 = 15.0;
+pub const MAX_PART_SECONDS: f64 = 3600.0;
+
+// Frames are composed at 1080p, which card, label and caption sizes are
+// designed for, and scaled to the output resolution at the end
+const CANVAS_RESOLUTION: u32 = 1080;
+pub const DEFAULT_RESOLUTION: u32 = 1080;
+pub const DEFAULT_X264_PRESET: &str = "fast";
+pub const X264_PRESETS: &[&str] = &[
+    "ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow",
+];
+
+pub type ProgressFn<'a> = &'a (dyn Fn(RenderEvent) + Send + Sync);
+
+// How a background that does not match the output aspect ratio is fitted
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum FitMode {
+    // Scaled to fit inside the frame, with black bars
+    #[default]
+    Pad,
+    // Scaled to fill the frame, cutting off the overflowing edges
+    Crop,
+    // Fitted like `Pad`, over a blurred copy filling the frame
+    Blur,
+}
+
+impl std::str::FromStr for FitMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pad" => Ok(FitMode::Pad),
+            "crop" => Ok(FitMode::Crop),
+            "blur" => Ok(FitMode::Blur),
+            _ => Err(anyhow!("Unknown fit mode: {}", s)),
+        }
+    }
+}
+
+// Pan and zoom applied to still image backgrounds
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Motion {
+    #[default]
+    None,
+    ZoomIn,
+    ZoomOut,
+    PanLeft,
+    PanRight,
+    // One of the above, picked per video
+    Random,
+}
+
+impl std::str::FromStr for Motion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Motion::None),
+            "zoom_in" => Ok(Motion::ZoomIn),
+            "zoom_out" => Ok(Motion::ZoomOut),
+            "pan_left" => Ok(Motion::PanLeft),
+            "pan_right" => Ok(Motion::PanRight),
+            "random" => Ok(Motion::Random),
+            _ => Err(anyhow!("Unknown motion: {}", s)),
+        }
+    }
+}
+
+// Where playback of a video background begins
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum BackgroundStart {
+    #[default]
+    Beginning,
+    // Seconds into the clip
+    At(f64),
+    // Anywhere that leaves enough clip to cover the narration
+    Random,
+}
+
+impl std::str::FromStr for BackgroundStart {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "random" {
+            return Ok(BackgroundStart::Random);
+        }
+
+        match s.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(BackgroundStart::At(seconds)),
+            _ => Err(anyhow!("bg_start must be a number of seconds or \"random\", got: {}", s)),
+        }
+    }
+}
+
+// Encoding settings shared by every video a server or CLI renders
+#[derive(Clone)]
+pub struct OutputSettings {
+    // Short side of the frame: the height of 16:9 videos, the width of 9:16 ones
+    pub resolution: u32,
+    pub x264_preset: String,
+    // Base caption size, which libass scales from a 288 pixel tall canvas
+    pub caption_font_size: u32,
+    pub caption_font_size_vertical: u32,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            resolution: DEFAULT_RESOLUTION,
+            x264_preset: DEFAULT_X264_PRESET.to_string(),
+            caption_font_size: DEFAULT_CAPTION_FONT_SIZE,
+            caption_font_size_vertical: DEFAULT_CAPTION_FONT_SIZE_VERTICAL,
+        }
+    }
+}
+
+impl OutputSettings {
+    pub fn font_size(&self, aspect_ratio: &str) -> u32 {
+        if aspect_ratio == "9:16" {
+            self.caption_font_size_vertical
+        } else {
+            self.caption_font_size
+        }
+    }
+}
+
+// Width and height of a 16:9 or 9:16 frame whose short side is `resolution`,
+// kept even for yuv420p
+fn frame_size(aspect_ratio: &str, resolution: u32) -> (u32, u32) {
+    let long = (resolution * 16 / 9) / 2 * 2;
+    if aspect_ratio == "9:16" {
+        (resolution, long)
+    } else {
+        (long, resolution)
+    }
+}
+
+pub struct VideoOptions {
+    pub aspect_ratio: String,
+    pub fit_mode: FitMode,
+    // Only used for image backgrounds
+    pub motion: Motion,
+    // Only used for video backgrounds
+    pub bg_start: BackgroundStart,
+    // Splits longer stories into numbered parts
+    pub max_part_seconds: Option<f64>,
+    pub caption_align: CaptionAlignment,
+    pub caption_style: CaptionStyle,
+    // Shows a title card for the post while its title is read
+    pub post: Option<PostMeta>,
+    // Explicit cards, e.g. one per narrated comment; replaces the title card
+    pub cards: Vec<Card>,
+    // Background music ducked under the narration
+    pub music: Option<MusicOptions>,
+    pub loudness: LoudnessTarget,
+    pub output: OutputSettings,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            aspect_ratio: "16:9".to_string(),
+            fit_mode: FitMode::default(),
+            motion: Motion::default(),
+            bg_start: BackgroundStart::default(),
+            max_part_seconds: None,
+            caption_align: CaptionAlignment::default(),
+            caption_style: CaptionStyle::default(),
+            post: None,
+            cards: Vec::new(),
+            music: None,
+            loudness: LoudnessTarget::default(),
+            output: OutputSettings::default(),
+        }
+    }
+}
+
+// One rendered video; a story split into parts has several
+pub struct VideoPart {
+    pub video_filename: String,
+    // Span of the narration this part covers
+    pub start: f64,
+    pub end: f64,
+    pub caption_files: Vec<CaptionFile>,
+}
+
+pub struct VideoOutput {
+    // In order; a single element unless `max_part_seconds` split the story
+    pub parts: Vec<VideoPart>,
+    pub caption_text: Option<String>,
+    // Narration level before and after normalization
+    pub loudness: Option<LoudnessReport>,
+}
+
+// Everything the parts of one video share
+struct RenderContext<'a> {
+    workspace: &'a Workspace,
+    aspect_ratio: &'a str,
+    width: u32,
+    height: u32,
+    bg_path: &'a str,
+    is_image: bool,
+    bg_loop: bool,
+    bg_seek: f64,
+    audio_path: &'a str,
+    music_path: Option<&'a str>,
+    options: &'a VideoOptions,
+    words: &'a [WordTiming],
+    cards: &'a [Card],
+    part_count: usize,
+}
+
+// Renders into the workspace; intermediate files are left for the workspace
+// to clean up
 pub async fn process_video(
-    bg_file_data: Vec<u8>,
-    bg_filename: String,
-    aspect_ratio: String,
+    workspace: &Workspace,
+    background: BackgroundSource,
     audio_filename: String,
     original_text: String,
-) -> Result<(String, Option<String>, Option<String>)> {
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-
-    // Save background file
-    let safe_bg_filename = format!(
-        "bg_{}_{}",
-        timestamp,
-        bg_filename
-            .chars()
-            .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '_')
-            .collect::<String>()
-    );
-    let bg_path = format!("uploads/{}", safe_bg_filename);
-    std::fs::write(&bg_path, bg_file_data)?;
-
-    let output_filename = format!("video_{}.mp4", timestamp);
-    let output_path = format!("uploads/{}", output_filename);
+    mut options: VideoOptions,
+    progress: ProgressFn<'_>,
+) -> Result<VideoOutput> {
+    let aspect_ratio = options.aspect_ratio.clone();
+
+    // Uploads are already saved in the workspace; both are read in place
+    let bg_path = match background {
+        BackgroundSource::Upload(path) | BackgroundSource::Library(path) => path,
+    };
 
     // Convert audio to ensure compatibility
-    let temp_audio = format!("uploads/converted_{}.aac", timestamp);
-    let audio_path = format!("uploads/{}", audio_filename);
-
-    let audio_convert = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i",
-            &audio_path,
-            "-ar",
-            "44100",
-            "-ac",
-            "2",
-            "-c:a",
-            "aac",
-            &temp_audio,
-        ])
-        .output()?;
+    let temp_audio = workspace.path("converted.aac");
+    let audio_path = upload_path(&audio_filename);
+
+    progress(RenderEvent::Stage(RenderStage::AudioConvert));
+    let measured = measure_loudness(&audio_path, options.loudness).await?;
+    let loudnorm = normalize_filter(options.loudness, &measured);
+    if loudnorm.is_none() {
+        log::warn!("Narration in {} is silent, skipping loudness normalization", audio_filename);
+    }
+
+    let mut convert_args = vec!["-y", "-hide_banner", "-i", &audio_path];
+    if let Some(ref filter) = loudnorm {
+        convert_args.extend(["-af", filter]);
+    }
+    convert_args.extend(["-ar", "44100", "-ac", "2", "-c:a", "aac", &temp_audio]);
+
+    let audio_convert = Command::new("ffmpeg").args(&convert_args).output().await?;
 
     if !audio_convert.status.success() {
         return Err(anyhow!(
@@ -53,71 +298,178 @@ pub async fn process_video(
         ));
     }
 
+    let loudness = match loudnorm {
+        Some(_) => {
+            let stderr = String::from_utf8_lossy(&audio_convert.stderr);
+            match loudness_report(options.loudness, &measured, &stderr) {
+                Ok(report) => Some(report),
+                Err(e) => {
+                    log::warn!("Could not read normalized loudness: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
     // Get audio duration
-    let duration_output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-show_entries",
-            "format=duration",
-            "-of",
-            "json",
+    progress(RenderEvent::Stage(RenderStage::Probe));
+    let duration = probe_duration(&temp_audio).await?;
+
+    // Work out word timings; the captions themselves are written per part
+    progress(RenderEvent::Stage(RenderStage::Captions));
+    let words = if !original_text.trim().is_empty() {
+        resolve_word_timings(
+            options.caption_align,
+            load_word_timings(&audio_filename),
             &temp_audio,
-        ])
-        .output()?;
+            &original_text,
+            duration,
+        )
+        .await
+    } else {
+        Vec::new()
+    };
 
-    if !duration_output.status.success() {
-        return Err(anyhow!("Could not determine audio duration"));
-    }
+    // Determine if background is image or video
+    let is_image = is_image_file(&bg_path);
 
-    let duration_str = String::from_utf8_lossy(&duration_output.stdout);
-    let duration_json: serde_json::Value = serde_json::from_str(&duration_str)?;
-    let duration = duration_json["format"]["duration"]
-        .as_str()
-        .and_then(|s| s.parse::<f64>().ok())
-        .ok_or_else(|| anyhow!("Invalid duration format"))?;
+    // Size of the canvas the frame is composed on
+    let (width, height) = frame_size(&aspect_ratio, CANVAS_RESOLUTION);
 
-    // Generate captions
-    let (srt_content, caption_text, srt_filename) = if !original_text.trim().is_empty() {
-        match generate_captions_from_text(&original_text, duration) {
-            Ok((content, text)) => {
-                if !content.is_empty() {
-                    match save_srt_file(&content).await {
-                        Ok(filename) => (Some(content), Some(text), Some(filename)),
-                        Err(_) => (None, None, None),
-                    }
-                } else {
-                    (None, None, None)
+    // Play a long enough clip straight through from the chosen start, and
+    // only loop clips shorter than the narration
+    let mut bg_loop = false;
+    let mut bg_seek = 0.0;
+    if !is_image {
+        match probe_duration(&bg_path).await {
+            Ok(bg_duration) if bg_duration >= duration => {
+                bg_seek = background_seek(options.bg_start, bg_duration, duration);
+            }
+            Ok(bg_duration) => {
+                if options.bg_start != BackgroundStart::Beginning {
+                    log::info!(
+                        "Background is {:.1}s, shorter than the {:.1}s narration; looping from the start",
+                        bg_duration,
+                        duration
+                    );
                 }
+                bg_loop = true;
+            }
+            Err(e) => {
+                log::warn!("Could not probe background duration, looping it: {}", e);
+                bg_loop = true;
             }
-            Err(_) => (None, None, None),
         }
+    }
+
+    let music_path = match options.music.as_ref().map(|music| &music.source) {
+        Some(MusicSource::Upload(path) | MusicSource::Library(path)) => Some(path.clone()),
+        None => None,
+    };
+
+    let cards = match options.post {
+        Some(ref post) if options.cards.is_empty() => vec![title_card(post, &words, duration)],
+        _ => std::mem::take(&mut options.cards),
+    };
+
+    let spans = split_parts(&words, duration, options.max_part_seconds)?;
+    let context = RenderContext {
+        workspace,
+        aspect_ratio: &aspect_ratio,
+        width,
+        height,
+        bg_path: &bg_path,
+        is_image,
+        bg_loop,
+        bg_seek,
+        audio_path: &temp_audio,
+        music_path: music_path.as_deref(),
+        options: &options,
+        words: &words,
+        cards: &cards,
+        part_count: spans.len(),
+    };
+
+    progress(RenderEvent::Stage(RenderStage::Encode));
+    let mut parts = Vec::new();
+    for (index, &(start, end)) in spans.iter().enumerate() {
+        // Report encode progress over the whole story, not per part
+        let part_progress = |event: RenderEvent| match event {
+            RenderEvent::Progress(percent) => progress(RenderEvent::Progress(
+                (start + percent / 100.0 * (end - start)) / duration * 100.0,
+            )),
+            other => progress(other),
+        };
+
+        parts.push(render_part(&context, index, start, end, &part_progress).await?);
+    }
+
+    let cue_count: usize = parts
+        .iter()
+        .filter(|part| !part.caption_files.is_empty())
+        .map(|part| build_cues(&words_between(&words, part.start, part.end)).len())
+        .sum();
+    let caption_text = if cue_count > 0 {
+        Some(format!("Generated {} caption segments", cue_count))
     } else {
-        (None, None, None)
+        None
     };
 
-    // Determine if background is image or video
-    let ext = bg_filename.to_lowercase();
-    let is_image = ext.ends_with(".jpg")
-        || ext.ends_with(".jpeg")
-        || ext.ends_with(".png")
-        || ext.ends_with(".bmp")
-        || ext.ends_with(".gif")
-        || ext.ends_with(".webp");
-
-    // Set up video scaling
-    let scale_filter = if aspect_ratio == "9:16" {
-        "scale=1080:1920:force_original_aspect_ratio=decrease,pad=1080:1920:(ow-iw)/2:(oh-ih)/2"
+    Ok(VideoOutput {
+        parts,
+        caption_text,
+        loudness,
+    })
+}
+
+// Renders the narration between `start` and `end` as one video, with its own
+// captions and, when there are several parts, a "Part N/M" label
+async fn render_part(
+    context: &RenderContext<'_>,
+    index: usize,
+    start: f64,
+    end: f64,
+    progress: ProgressFn<'_>,
+) -> Result<VideoPart> {
+    let options = context.options;
+    let aspect_ratio = context.aspect_ratio;
+    let duration = end - start;
+
+    // Parts only get a suffix when there is more than one
+    let suffix = if context.part_count > 1 {
+        format!("_part{}", index + 1)
     } else {
-        "scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2"
+        String::new()
+    };
+    let output_filename = context.workspace.file(&format!("video{}.mp4", suffix));
+    let output_path = upload_path(&output_filename);
+
+    // Generate captions
+    let cues = build_cues(&words_between(context.words, start, end));
+    let caption_files = if cues.is_empty() {
+        Vec::new()
+    } else {
+        let stem = context.workspace.file(&format!("captions{}", suffix));
+        let font_size = options.output.font_size(aspect_ratio);
+        match save_caption_files(&cues, aspect_ratio, options.caption_style, font_size, &stem).await {
+            Ok(files) => files,
+            Err(e) => {
+                log::warn!("Could not save caption files: {}", e);
+                Vec::new()
+            }
+        }
     };
 
     // Create FFmpeg command
     let mut ffmpeg_cmd = vec!["ffmpeg", "-y"];
 
     let duration_str = duration.to_string();
-    
-    if is_image {
+    // Later parts carry on where the previous part's background left off
+    let bg_seek_str = format!("{:.3}", context.bg_seek + start);
+    let audio_seek_str = format!("{:.3}", start);
+
+    if context.is_image {
         ffmpeg_cmd.extend([
             "-loop",
             "1",
@@ -126,25 +478,73 @@ pub async fn process_video(
             "-t",
             &duration_str,
         ]);
-    } else {
+    } else if context.bg_loop {
         ffmpeg_cmd.extend(["-stream_loop", "-1"]);
+    } else if context.bg_seek + start > 0.0 {
+        ffmpeg_cmd.extend(["-ss", &bg_seek_str]);
     }
+    ffmpeg_cmd.extend(["-i", context.bg_path]);
 
-    ffmpeg_cmd.extend(["-i", &bg_path, "-i", &temp_audio]);
+    if start > 0.0 {
+        ffmpeg_cmd.extend(["-ss", &audio_seek_str]);
+    }
+    ffmpeg_cmd.extend(["-t", &duration_str, "-i", context.audio_path]);
 
-    if !is_image {
+    if let Some(music_path) = context.music_path {
+        // Short tracks loop for the whole video
+        ffmpeg_cmd.extend(["-stream_loop", "-1", "-i", music_path]);
+    }
+
+    if !context.is_image {
         ffmpeg_cmd.extend(["-t", &duration_str]);
     }
 
     // Add filter complex
-    let mut filter_complex = format!("[0:v]{}", scale_filter);
+    let mut filter_complex = fit_filter(options.fit_mode, context.width, context.height);
+    if context.is_image {
+        if let Some(motion) = motion_filter(options.motion, context.width, context.height, duration)
+        {
+            filter_complex.push_str(&motion);
+        }
+    }
+
+    // Cards go under the captions so the captions stay readable
+    let file_prefix = context.workspace.path(&format!("part{}", index + 1));
+    let cards: Vec<Card> = context
+        .cards
+        .iter()
+        .filter(|card| card.end > start && card.start < end)
+        .map(|card| Card {
+            start: (card.start - start).max(0.0),
+            end: card.end.min(end) - start,
+            ..card.clone()
+        })
+        .collect();
+    if !cards.is_empty() {
+        filter_complex.push_str(&card_filters(&cards, aspect_ratio, &file_prefix)?);
+    }
 
-    if let Some(ref srt_content) = srt_content {
-        let srt_path = format!("uploads/captions_{}.srt", timestamp);
-        std::fs::write(&srt_path, srt_content)?;
+    if context.part_count > 1 {
+        let label_path = format!("{}_label.txt", file_prefix);
+        std::fs::write(&label_path, format!("Part {}/{}", index + 1, context.part_count))?;
+        filter_complex.push_str(&part_label_filter(&label_path, aspect_ratio));
+    }
+
+    let caption_path = |format: CaptionFormat| {
+        caption_files
+            .iter()
+            .find(|file| file.format == format)
+            .map(|file| upload_path(&file.filename))
+    };
 
-        let base_font_size = if aspect_ratio == "16:9" { 32 } else { 36 };
-        let font_size = (base_font_size as f32 * 0.7) as u32;
+    if options.caption_style == CaptionStyle::Karaoke {
+        if let Some(ass_path) = caption_path(CaptionFormat::Ass) {
+            // The ASS file carries its own styling, so no force_style here
+            let ass_path_escaped = ass_path.replace("\\", "\\\\").replace(":", "\\:");
+            filter_complex.push_str(&format!(",subtitles='{}'", ass_path_escaped));
+        }
+    } else if let Some(srt_path) = caption_path(CaptionFormat::Srt) {
+        let font_size = (options.output.font_size(aspect_ratio) as f32 * 0.7) as u32;
         let base_margin_v = if aspect_ratio == "16:9" { 40 } else { 80 };
         let margin_v = (base_margin_v as f32 * 0.7) as u32;
 
@@ -156,33 +556,343 @@ pub async fn process_video(
         filter_complex.push_str(&subtitle_filter);
     }
 
+    if options.output.resolution != CANVAS_RESOLUTION {
+        let (width, height) = frame_size(aspect_ratio, options.output.resolution);
+        filter_complex.push_str(&format!(",scale={}:{}", width, height));
+    }
     filter_complex.push_str("[v]");
 
+    let audio_map = match options.music {
+        Some(ref music) => {
+            filter_complex.push(';');
+            filter_complex.push_str(&music_filter("[1:a]", "[2:a]", music.volume, music.fade, duration));
+            "[a]"
+        }
+        _ => "1:a",
+    };
+
     ffmpeg_cmd.extend(["-filter_complex", &filter_complex]);
-    ffmpeg_cmd.extend(["-map", "[v]", "-map", "1:a"]);
-    ffmpeg_cmd.extend(["-c:v", "libx264", "-preset", "fast", "-c:a", "copy"]);
+    ffmpeg_cmd.extend(["-map", "[v]", "-map", audio_map]);
+    ffmpeg_cmd.extend(["-c:v", "libx264", "-preset", &options.output.x264_preset]);
+    if audio_map == "[a]" {
+        // The mix is new audio, so it has to be encoded
+        ffmpeg_cmd.extend(["-c:a", "aac", "-b:a", "192k"]);
+    } else {
+        ffmpeg_cmd.extend(["-c:a", "copy"]);
+    }
+    ffmpeg_cmd.extend(["-progress", "pipe:1", "-nostats"]);
     ffmpeg_cmd.extend(["-movflags", "+faststart", "-shortest", &output_path]);
 
-    // Execute FFmpeg
-    let ffmpeg_output = Command::new("ffmpeg")
-        .args(&ffmpeg_cmd[1..]) // Skip "ffmpeg" as it's the command name
-        .output()?;
-
-    // Clean up temporary files
-    let _ = std::fs::remove_file(&temp_audio);
-    let _ = std::fs::remove_file(&bg_path);
-
-    if !ffmpeg_output.status.success() {
-        return Err(anyhow!(
-            "Video creation failed: {}",
-            String::from_utf8_lossy(&ffmpeg_output.stderr)
-        ));
-    }
+    // Execute FFmpeg, skipping "ffmpeg" as it's the command name
+    run_ffmpeg_with_progress(&ffmpeg_cmd[1..], duration, progress).await?;
 
     // Verify output exists
     if !std::path::Path::new(&output_path).exists() {
         return Err(anyhow!("Output video not generated"));
     }
 
-    Ok((output_filename, caption_text, srt_filename))
+    Ok(VideoPart {
+        video_filename: output_filename,
+        start,
+        end,
+        caption_files,
+    })
+}
+
+// Cuts the narration into spans of at most `max_part_seconds`, preferring the
+// pause after the last sentence that fits. Falls back to the last word that
+// fits, and to a hard cut when even a single word is too long.
+fn split_parts(
+    words: &[WordTiming],
+    duration: f64,
+    max_part_seconds: Option<f64>,
+) -> Result<Vec<(f64, f64)>> {
+    let Some(max) = max_part_seconds else {
+        return Ok(vec![(0.0, duration)]);
+    };
+    // Every cut has to move forward, or the loop below never ends
+    if !max.is_finite() || max <= 0.0 {
+        return Err(anyhow!("Part length must be above 0 seconds, got {}", max));
+    }
+
+    let mut spans = Vec::new();
+    let mut start = 0.0;
+
+    while duration - start > max {
+        let limit = start + max;
+        // Don't cut so early that the part ends up much shorter than the limit
+        let earliest = start + max * MIN_PART_FRACTION;
+
+        let fitting: Vec<usize> = (0..words.len())
+            .filter(|&i| words[i].end > earliest && words[i].end <= limit)
+            .collect();
+        let cut_after = fitting
+            .iter()
+            .rev()
+            .find(|&&i| ends_sentence(&words[i].word))
+            .or(fitting.last());
+
+        let cut = match cut_after {
+            // Cut in the middle of the pause following the word
+            Some(&i) => match words.get(i + 1) {
+                Some(next) if next.start <= limit => (words[i].end + next.start) / 2.0,
+                _ => words[i].end,
+            },
+            None => limit,
+        };
+
+        spans.push((start, cut));
+        start = cut;
+    }
+
+    spans.push((start, duration));
+    Ok(spans)
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')', '”', '’'])
+        .ends_with(['.', '!', '?'])
+}
+
+// Words spoken between `start` and `end`, with times relative to `start`
+fn words_between(words: &[WordTiming], start: f64, end: f64) -> Vec<WordTiming> {
+    words
+        .iter()
+        .filter(|word| word.start >= start && word.start < end)
+        .map(|word| WordTiming {
+            word: word.word.clone(),
+            start: word.start - start,
+            end: word.end.min(end) - start,
+        })
+        .collect()
+}
+
+// "Part N/M" across the top of the frame, read from `label_path`
+fn part_label_filter(label_path: &str, aspect_ratio: &str) -> String {
+    let font_size = if aspect_ratio == "9:16" { 64 } else { 52 };
+    let label_path_escaped = label_path.replace('\\', "\\\\").replace(':', "\\:");
+    format!(
+        ",drawtext=textfile='{}':expansion=none:x=(w-text_w)/2:y=h*0.05:fontsize={}:fontcolor=white:box=1:boxcolor=black@0.55:boxborderw=18",
+        label_path_escaped, font_size
+    )
+}
+
+// Start offset into a background of `bg_duration` seconds that still leaves
+// `duration` seconds to play
+fn background_seek(start: BackgroundStart, bg_duration: f64, duration: f64) -> f64 {
+    let latest = (bg_duration - duration).max(0.0);
+    match start {
+        BackgroundStart::Beginning => 0.0,
+        BackgroundStart::At(seconds) => seconds.min(latest),
+        BackgroundStart::Random => rand::thread_rng().gen_range(0.0..=latest),
+    }
+}
+
+// Starts the video filter chain on `[0:v]`, leaving a `width`x`height` frame
+// for the cards and captions to be drawn on
+fn fit_filter(fit_mode: FitMode, width: u32, height: u32) -> String {
+    match fit_mode {
+        FitMode::Pad => format!(
+            "[0:v]scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+            w = width,
+            h = height
+        ),
+        FitMode::Crop => format!(
+            "[0:v]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},setsar=1",
+            w = width,
+            h = height
+        ),
+        // The blurred copy is shrunk before blurring, which is much cheaper
+        // than blurring at full size and looks the same
+        FitMode::Blur => format!(
+            "[0:v]split=2[bg][fg];\
+             [bg]scale={sw}:{sh}:force_original_aspect_ratio=increase,crop={sw}:{sh},boxblur=10:2,scale={w}:{h},setsar=1[blurred];\
+             [fg]scale={w}:{h}:force_original_aspect_ratio=decrease,setsar=1[fitted];\
+             [blurred][fitted]overlay=(W-w)/2:(H-h)/2",
+            w = width,
+            h = height,
+            sw = width / 4,
+            sh = height / 4
+        ),
+    }
+}
+
+// Appends a zoompan to the fitted frame whose movement spans `duration`.
+// The frame is upscaled first so zoompan's whole-pixel crop positions do not
+// make the image jitter.
+fn motion_filter(motion: Motion, width: u32, height: u32, duration: f64) -> Option<String> {
+    let motion = match motion {
+        Motion::None => return None,
+        Motion::Random => {
+            let presets = [Motion::ZoomIn, Motion::ZoomOut, Motion::PanLeft, Motion::PanRight];
+            presets[rand::thread_rng().gen_range(0..presets.len())]
+        }
+        motion => motion,
+    };
+
+    let frames = ((duration * MOTION_FPS as f64).ceil() as u64).max(1);
+    let t = format!("(on/{})", frames);
+    let max_zoom = 1.0 + MOTION_ZOOM;
+
+    let (zoom, x, y) = match motion {
+        Motion::ZoomIn => (
+            format!("1+{}*{}", MOTION_ZOOM, t),
+            "iw/2-(iw/zoom/2)".to_string(),
+            "ih/2-(ih/zoom/2)".to_string(),
+        ),
+        Motion::ZoomOut => (
+            format!("{}-{}*{}", max_zoom, MOTION_ZOOM, t),
+            "iw/2-(iw/zoom/2)".to_string(),
+            "ih/2-(ih/zoom/2)".to_string(),
+        ),
+        // Panning needs some zoom to have room to move
+        Motion::PanLeft => (
+            max_zoom.to_string(),
+            format!("(iw-iw/zoom)*(1-{})", t),
+            "ih/2-(ih/zoom/2)".to_string(),
+        ),
+        Motion::PanRight => (
+            max_zoom.to_string(),
+            format!("(iw-iw/zoom)*{}", t),
+            "ih/2-(ih/zoom/2)".to_string(),
+        ),
+        Motion::None | Motion::Random => return None,
+    };
+
+    Some(format!(
+        ",scale={}:{},zoompan=z='{}':x='{}':y='{}':d=1:s={}x{}:fps={}",
+        width * MOTION_OVERSAMPLE,
+        height * MOTION_OVERSAMPLE,
+        zoom,
+        x,
+        y,
+        width,
+        height,
+        MOTION_FPS
+    ))
+}
+
+pub fn safe_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '_')
+        .collect()
+}
+
+// Runs ffmpeg with `-progress pipe:1` already in `args`, turning the reported
+// output timestamp into a percentage of `duration`.
+async fn run_ffmpeg_with_progress(
+    args: &[&str],
+    duration: f64,
+    progress: ProgressFn<'_>,
+) -> Result<()> {
+    let mut child = Command::new("ffmpeg")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain stderr in the background so ffmpeg never blocks on a full pipe
+    let mut stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow!("Could not capture ffmpeg stderr"))?;
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf).await;
+        String::from_utf8_lossy(&buf).to_string()
+    });
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("Could not capture ffmpeg progress output"))?;
+    let mut lines = BufReader::new(stdout).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        // Despite the name, ffmpeg reports out_time_ms in microseconds
+        if let Some(value) = line.strip_prefix("out_time_ms=") {
+            if let Ok(micros) = value.trim().parse::<f64>() {
+                if duration > 0.0 {
+                    let percent = (micros / 1_000_000.0 / duration * 100.0).clamp(0.0, 100.0);
+                    progress(RenderEvent::Progress(percent));
+                }
+            }
+        } else if line.trim() == "progress=end" {
+            progress(RenderEvent::Progress(100.0));
+        }
+    }
+
+    let status = child.wait().await?;
+    let stderr_output = stderr_task.await.unwrap_or_default();
+
+    if !status.success() {
+        return Err(anyhow!("Video creation failed: {}", stderr_output));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(timings: &[(&str, f64, f64)]) -> Vec<WordTiming> {
+        timings
+            .iter()
+            .map(|&(word, start, end)| WordTiming {
+                word: word.to_string(),
+                start,
+                end,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_parts_keeps_short_narration_whole() {
+        let words = words(&[("Hello", 0.0, 1.0), ("there.", 1.0, 2.0)]);
+        assert_eq!(split_parts(&words, 30.0, None).unwrap(), vec![(0.0, 30.0)]);
+        assert_eq!(split_parts(&words, 30.0, Some(60.0)).unwrap(), vec![(0.0, 30.0)]);
+    }
+
+    #[test]
+    fn split_parts_cuts_in_the_pause_after_a_sentence() {
+        let words = words(&[
+            ("One", 0.0, 1.0),
+            ("two.", 1.0, 6.0),
+            ("three", 6.5, 8.0),
+            ("four", 8.0, 9.5),
+            ("five.", 10.0, 12.0),
+            ("six", 12.5, 14.0),
+        ]);
+        assert_eq!(split_parts(&words, 14.0, Some(10.0)).unwrap(), vec![(0.0, 6.25), (6.25, 14.0)]);
+    }
+
+    #[test]
+    fn split_parts_falls_back_to_the_last_word_that_fits() {
+        let words = words(&[
+            ("a", 0.0, 3.0),
+            ("b", 3.2, 7.0),
+            ("c", 7.2, 9.0),
+            ("d", 9.5, 13.0),
+        ]);
+        assert_eq!(split_parts(&words, 13.0, Some(10.0)).unwrap(), vec![(0.0, 9.25), (9.25, 13.0)]);
+    }
+
+    #[test]
+    fn split_parts_rejects_part_lengths_that_never_advance() {
+        let words = words(&[("Hello.", 0.0, 1.0)]);
+        for max in [0.0, -5.0, f64::NAN, f64::INFINITY] {
+            assert!(split_parts(&words, 30.0, Some(max)).is_err(), "{}", max);
+        }
+    }
+
+    #[test]
+    fn split_parts_hard_cuts_words_longer_than_a_part() {
+        let words = words(&[("aaaah", 0.0, 25.0)]);
+        assert_eq!(
+            split_parts(&words, 25.0, Some(10.0)).unwrap(),
+            vec![(0.0, 10.0), (10.0, 20.0), (20.0, 25.0)]
+        );
+    }
 }
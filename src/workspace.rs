@@ -0,0 +1,101 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+// Everything the pipeline writes lives under here and is served at /download
+pub const DEFAULT_UPLOAD_DIR: &str = "uploads";
+
+// Cached voice samples, kept out of retention sweeps
+pub const VOICE_SAMPLE_DIR: &str = "voice-samples";
+
+static UPLOAD_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Picks the upload directory; only takes effect before anything has used it
+pub fn set_upload_dir(dir: &Path) {
+    let _ = UPLOAD_DIR.set(dir.to_path_buf());
+}
+
+pub fn upload_dir() -> &'static Path {
+    UPLOAD_DIR.get_or_init(|| PathBuf::from(DEFAULT_UPLOAD_DIR))
+}
+
+// Path of a pipeline filename, which are all relative to the upload directory
+pub fn upload_path(filename: &str) -> String {
+    upload_dir().join(filename).to_string_lossy().to_string()
+}
+
+// Ids of workspaces that have not been dropped yet, so cleanup jobs can tell a
+// render in progress from leftovers
+static ACTIVE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn is_active(id: &str) -> bool {
+    ACTIVE.lock().unwrap().iter().any(|active| active == id)
+}
+
+// A directory of its own for one request, `uploads/<uuid>/`, so concurrent
+// requests never write to the same file. Files are named relative to
+// uploads/ like every other filename in the pipeline, e.g. `<uuid>/video.mp4`.
+// When the workspace is dropped everything not marked with `keep` is removed,
+// whether the request succeeded or failed.
+pub struct Workspace {
+    id: String,
+    dir: PathBuf,
+    kept: Mutex<HashSet<String>>,
+}
+
+impl Workspace {
+    pub fn create() -> Result<Self> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let dir = upload_dir().join(&id);
+        // Registered first so a sweep never sees the new directory as unused
+        ACTIVE.lock().unwrap().push(id.clone());
+        let workspace = Workspace {
+            id,
+            dir,
+            kept: Mutex::new(HashSet::new()),
+        };
+        std::fs::create_dir_all(&workspace.dir)?;
+
+        Ok(workspace)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // `name` inside the workspace, relative to uploads/
+    pub fn file(&self, name: &str) -> String {
+        format!("{}/{}", self.id, name)
+    }
+
+    // `name` inside the workspace, as a path ffmpeg and std::fs can open
+    pub fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().to_string()
+    }
+
+    // Marks an output, given relative to uploads/, to survive cleanup
+    pub fn keep(&self, filename: &str) {
+        if let Some(name) = filename.strip_prefix(&format!("{}/", self.id)) {
+            self.kept.lock().unwrap().insert(name.to_string());
+        }
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let kept = self.kept.lock().unwrap();
+        if let Ok(entries) = std::fs::read_dir(&self.dir) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !kept.contains(&name) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        // Only succeeds once nothing was kept
+        let _ = std::fs::remove_dir(&self.dir);
+        ACTIVE.lock().unwrap().retain(|active| *active != self.id);
+    }
+}